@@ -1,6 +1,15 @@
 mod tok_trainer;
 mod tok_codec;
-use std::{env, fs::File, path::Path, io::{BufReader, Read}, cmp::min};
+mod tok_entropy;
+mod tok_suffix_array;
+mod tok_byte_pair;
+mod tok_unigram;
+mod tok_cdc;
+mod tok_count_min;
+mod tok_spill;
+mod tok_input;
+use tok_input::InputBytes;
+use std::{env, fs::File, path::{Path, PathBuf}, io::{BufReader, Read}, cmp::min};
 
 
 mod debug_enum {
@@ -24,17 +33,39 @@ macro_rules! impl_has_checks {
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TokenizerParameters {
 	multi_threaded: Option<usize>,
 	pub dbg_lv: u8,
-	pub bin_dat: Option<Vec<u8>>,
+	pub bin_dat: Option<InputBytes>,
+	// Where each file is in `bin_dat`; no token spans two files. None for a single file
+	pub file_bounds: Option<Vec<std::ops::Range<usize>>>,
 	pub bytes_to_read: Option<u64>,
+	// Map the input file instead of reading it, when it's a single regular file
+	pub memory_map: bool,
 	pub trainer_chk_bytes: Option<usize>,
+	pub trainer_chk_overlap: Option<usize>,
+	pub chunking_mode: tok_trainer::ChunkingMode,
+	pub encode_mode: tok_codec::EncodeMode,
+	pub output_path: Option<String>,
+	pub count_backend: tok_trainer::CountBackend,
+	pub max_token_len: Option<usize>,
+	pub vocab_size: Option<usize>,
+	// Approximate bytes the train counts may take, the lowest counts are pruned to fit
+	pub max_mem: Option<usize>,
+	pub pruning_policy: tok_trainer::PruningPolicy,
+	// Where the summed models go over "max_mem=" instead of being pruned
+	pub spill_runs: Option<tok_spill::SpillRuns>,
+	// Filled by the trainer: how many keys the pruning dropped
+	pub pruning_metrics: tok_trainer::PruningMetrics,
+	// Train while reading instead of reading everything first
+	pub streaming: bool,
+	pub trainer_mode: tok_trainer::TrainerMode,
 }
 
 impl TokenizerParameters {
 	#![allow(dead_code)]
+	#![allow(clippy::bad_bit_mask)] // `has_silent()` masks with zero on purpose
 	impl_has_checks! {
 		has_silent => debug_enum::SILENT,
 		has_error => debug_enum::ERROR,
@@ -44,13 +75,27 @@ impl TokenizerParameters {
 		has_verbose => debug_enum::VERBOSE,
 		has_lengthy => debug_enum::LENGTHY
 	}
+
+	pub fn file_bounds(&self) -> Vec<std::ops::Range<usize>> {
+		match &self.file_bounds {
+			Some(file_bounds) => file_bounds.clone(),
+			#[allow(clippy::single_range_in_vec_init)] // One file covering all the bytes
+			None => vec![0..self.bin_dat.as_deref().map_or(0, <[u8]>::len)],
+		}
+	}
 }
 
 fn vaildate_parameters(args: &Vec<String>) {
-	let partial_arg_msg = (
-		// Try not to go over 80 characters!
-		format!("Usage: {} [parameter_1,parameter_2..] file\n", args[0])
-		+ "E.g.: tokenizer_trainer_bin v=0b0_1111,br=0x3fff,mt=0 pexels-pixabay-302743.jpg\n"
+	// Try not to go over 80 characters!
+	let partial_arg_msg =
+		format!("Usage: {} train [parameter_1,parameter_2..] path [path..]\n", args[0])
+		+ &format!("       {} encode [parameter_1,parameter_2..] model input output\n", args[0])
+		+ &format!("       {} decode [parameter_1,parameter_2..] model input output\n", args[0])
+		+ "E.g.: tokenizer_trainer_bin train v=0b0_1111,br=0x3fff,mt=0 pexels-pixabay-302743.jpg\n"
+		+ "      tokenizer_trainer_bin train 'g=*.svg|*.jpg,o=images.vocab.txt' images/ a.bin\n"
+		+ "      tar cf - images/ | tokenizer_trainer_bin train st=1,tcm=1 -\n"
+		+ "      tokenizer_trainer_bin encode em=2 output.vocab.txt in.jpg out.bpc\n"
+		+ "      tokenizer_trainer_bin decode v=0 output.vocab.txt out.bpc in.jpg\n"
 		+ "Parameters are separated by commas, non-matches are ignored:\n"
 		+ "  v=        Debug level; supports underscores, binary, decimal,\n"
 		+ "            hexadecimal, and combined levels.\n"
@@ -62,45 +107,222 @@ fn vaildate_parameters(args: &Vec<String>) {
 		+ "            v=0b1000 = Include info.\n"
 		+ "            v=0b1_0000 = Include verbose.\n"
 		+ "            v=0b10_0000 = Include lengthy.\n"
-		+ "  br=       Maximum bytes to read from the file.\n"
+		+ "  br=       Maximum bytes to read from the file(s) in total.\n"
+		+ "  mm=       Memory-map the input file. 1 = Map a single file (default\n"
+		+ "            with --features mmap), 0 = Read it into memory (default).\n"
+		+ "  st=       Streaming train. 1 = Train the chunks as they're read, for\n"
+		+ "            stdin (-) or huge inputs; tm=0 only, vs= keeps the top scores.\n"
+		+ "  g=        File name globs of train for the directories, separated\n"
+		+ "            by |; supports * and ?, quote them for the shell. Default: all files.\n"
 		+ "  mt=       Threads to use, 0 to detect system cores count.\n"
 		+ "            None to use single.\n"
 		+ "  tcb=      Training chunk bytes. Around 0(n ** 2 * 256) memory.\n"
-	);
+		+ "  tco=      Training chunk overlap bytes, less than tcb=. The\n"
+		+ "            neighbor chunks share them, a token in them can be\n"
+		+ "            counted twice. Default: 0\n"
+		+ "  tcm=      Training chunking mode. 0 = Fixed tcb= bytes (default),\n"
+		+ "            1 = Content-defined, tcb= bytes on average.\n"
+		+ "  cb=       Counting backend of train. 0 = BTreeMap (default),\n"
+		+ "            1 = Suffix array, near-linear memory for big tcb=.\n"
+		+ "            2 = Count-Min Sketch, approximate in a fixed memory.\n"
+		+ "  cme=      Count-Min error of cb=2 in parts per million of the\n"
+		+ "            counted substrings; keeps 1000000/cme= keys. Default: 1000\n"
+		+ "  ml=       Maximum token length in bytes for train and encode.\n"
+		+ "            Around O(n * ml * 256) memory instead with the BTreeMap.\n"
+		+ &format!("  vs=       Vocabulary size of train, at least {} for the\n", tok_codec::FIRST_LEARNED_TOKEN)
+		+ "            fallback bytes and special tokens. None to keep all,\n"
+		+ &format!("            or {} with tm=2.\n", tok_unigram::DEFAULT_VOCAB_SIZE)
+		+ "  pp=       Pruning policy of the train counts, cb=0 only.\n"
+		+ "            0 = Keep all, 1 = Drop the counts of ppc= or less\n"
+		+ "            every ppn= substrings (default), 2 = Keep the ppn=\n"
+		+ "            highest counts, 3 = Space-Saving with ppn= counts.\n"
+		+ "  ppn=      Number of pp=. Default: 0xf_ffff with pp=1, else 0x1_0000\n"
+		+ "  ppc=      Minimum count of pp=1 to keep a key over. Default: 1\n"
+		+ "  max_mem=  Approximate memory of the train counts in bytes; the\n"
+		+ "            lowest counts are pruned to fit, so a big tcb= can't\n"
+		+ "            run out of memory. tm=0 and the tm=2 seeds. None = No limit.\n"
+		+ "  spill=    Directory of train for the sorted runs; with it, the\n"
+		+ "            summed counts over max_mem= go to disk instead of being\n"
+		+ "            pruned, then they're merged at the end. tm=0 only.\n"
+		+ "  tm=       Training mode. 0 = Greedy substrings (default),\n"
+		+ "            1 = Classic BPE merges, also writes *.merges.txt.\n"
+		+ "            2 = Unigram language model, writes log-probabilities.\n"
+		+ &format!("  o=        Vocabulary output path of train. Default: {}\n", tok_trainer::DEFAULT_OUTPUT_PATH)
+		+ "  em=       Encoding mode. 0 = Greedy longest match,\n"
+		+ "            1 = Fewest tokens, 2 = Fewest estimated bits (default).\n";
+
+	let (expected_len, max_len) = match args.get(1).map(String::as_str) {
+		Some("train") => (4, usize::MAX),
+		Some("encode") | Some("decode") => (6, 6),
+		Some(command) => {
+			eprintln!("{}Hint: Unknown command {:?}; use train, encode, or decode.", partial_arg_msg, command);
+			std::process::exit(1);
+		}
+		None => {
+			eprintln!("{}Hint: Please specify a command.", partial_arg_msg);
+			std::process::exit(1);
+		}
+	};
 
-	if args.len() >= 4 {
-		eprintln!("{}Hint: Please give 3 parameters; received {} parameters: {:?}", partial_arg_msg, args.len(), args);
+	if args.len() > max_len {
+		eprintln!("{}Hint: Please give {} parameters; received {} parameters: {:?}", partial_arg_msg, expected_len, args.len(), args);
 		std::process::exit(1);
 	} else if args.len() == 2 {
-		eprintln!("{}Hint: Please specify the input file path.", partial_arg_msg);
-		std::process::exit(1);
-	} else if args.len() == 1 {
 		eprintln!("{}Hint: Please specify options.", partial_arg_msg);
 		std::process::exit(1);
+	} else if args.len() < expected_len {
+		eprintln!("{}Hint: Please specify the file paths.", partial_arg_msg);
+		std::process::exit(1);
 	}
 }
 
-fn read_file(file_path: &String, bytes_to_read: Option<u64>) -> Option<Vec<u8>> {
-	let bytes_to_read = bytes_to_read.unwrap_or(u64::MAX);
-
-	let bin_file = match File::open(file_path) {
-		Ok(file) => Some(file),
+fn open_regular_file(file_path: &Path) -> File {
+	match File::open(file_path) {
+		Ok(file) => file,
 		Err(error) => {
-			eprintln!("Unable to open {}: {}", file_path, error);
-			let dir_path = Path::new(file_path).parent().unwrap();
-			if dir_path.exists() || dir_path.to_str().unwrap() == "" {
+			eprintln!("Unable to open {}: {}", file_path.display(), error);
+			let dir_path = file_path.parent().unwrap_or(Path::new(""));
+			if dir_path.exists() || dir_path.as_os_str().is_empty() {
 				eprintln!("Hint: Found the directory; check the file name: {:?}", file_path);
 			} else {
 				eprintln!("Hint: Check the directory {:?}", dir_path);
 			}
 			std::process::exit(1);
 		}
-	}.unwrap();
+	}
+}
 
-	let reader = BufReader::new(bin_file);
+fn open_file(file_path: &Path) -> Box<dyn Read> {
+	// "-" is the standard input, so `tar cf - dir | tokenizer_trainer_bin train st=1 -` works
+	// The paths stay `Path`s, so a file name that isn't UTF-8 still opens
+	if file_path == Path::new("-") {
+		return Box::new(std::io::stdin());
+	}
+	Box::new(BufReader::new(open_regular_file(file_path)))
+}
+
+fn read_file(file_path: &Path, bytes_to_read: Option<u64>) -> Vec<u8> {
+	let bytes_to_read = bytes_to_read.unwrap_or(u64::MAX);
 	let mut data = vec![];
-	reader.take(bytes_to_read).read_to_end(&mut data).expect("Unable to read file");
-	Some(data)
+	open_file(file_path).take(bytes_to_read).read_to_end(&mut data).expect("Unable to read file");
+	data
+}
+
+fn load_file(file_path: &Path, bytes_to_read: Option<u64>, memory_map: bool) -> InputBytes {
+	// Mapped unless it's the standard input or "mm=0"; pipes and such can't be mapped, so they're read
+	if !memory_map || file_path == Path::new("-") {
+		return read_file(file_path, bytes_to_read).into();
+	}
+	map_file(file_path, bytes_to_read)
+}
+
+#[cfg(feature = "mmap")]
+fn map_file(file_path: &Path, bytes_to_read: Option<u64>) -> InputBytes {
+	let file = open_regular_file(file_path);
+	match file.metadata() {
+		Ok(metadata) if metadata.is_file() => InputBytes::map(&file, bytes_to_read).unwrap_or_else(|error| {
+			eprintln!("Unable to map {}: {}", file_path.display(), error);
+			eprintln!("Hint: Try \"mm=0\" to read it into memory instead");
+			std::process::exit(1);
+		}),
+		_ => read_file(file_path, bytes_to_read).into(),
+	}
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map_file(file_path: &Path, bytes_to_read: Option<u64>) -> InputBytes {
+	// "mm=1" is refused without the "mmap" feature, but the tests still ask for it
+	read_file(file_path, bytes_to_read).into()
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+	// `*` matches any bytes and `?` one byte; backtracks to the last `*` only, so it's linear-ish
+	let (mut p, mut n) = (0, 0);
+	let mut last_star = None;
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+			p += 1;
+			n += 1;
+		} else if p < pattern.len() && pattern[p] == b'*' {
+			last_star = Some((p, n));
+			p += 1;
+		} else if let Some((star, star_n)) = last_star {
+			p = star + 1;
+			n = star_n + 1;
+			last_star = Some((star, star_n + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+fn collect_files(path: &Path, globs: &[&str], files: &mut Vec<PathBuf>) {
+	// read_dir() has no set order, so the entries are sorted; then br= cuts off the same files
+	// and the chunks don't change from run to run
+	// The globs only filter the files found in the directories, a file given by name is always read
+	if !path.is_dir() {
+		files.push(path.to_path_buf());
+		return;
+	}
+	// The file types don't follow symlinks, so a link back up the tree can't loop
+	// and a link to another tree doesn't read its files twice; symlinked directories are skipped
+	let mut entries: Vec<(PathBuf, std::fs::FileType)> = match std::fs::read_dir(path) {
+		Ok(entries) => entries.filter_map(|entry| entry.ok().and_then(|entry| Some((entry.path(), entry.file_type().ok()?)))).collect(),
+		Err(error) => {
+			eprintln!("Unable to read the directory {}: {}", path.display(), error);
+			std::process::exit(1);
+		}
+	};
+	entries.sort_by(|a, b| a.0.cmp(&b.0));
+	for (entry, file_type) in entries {
+		if file_type.is_dir() {
+			collect_files(&entry, globs, files);
+		} else if !(file_type.is_symlink() && entry.is_dir()) {
+			let name = entry.file_name().map_or(&b""[..], |name| name.as_encoded_bytes());
+			if globs.is_empty() || globs.iter().any(|glob| glob_match(glob.as_bytes(), name)) {
+				files.push(entry);
+			}
+		}
+	}
+}
+
+fn list_files(paths: &[String], globs: Option<&str>) -> Vec<PathBuf> {
+	let globs: Vec<&str> = globs.map_or(vec![], |globs| globs.split('|').collect());
+	let mut files = vec![];
+	for path in paths {
+		collect_files(Path::new(path), &globs, &mut files);
+	}
+	if files.is_empty() {
+		eprintln!("Error: No files matched in {:?}", paths);
+		std::process::exit(1);
+	}
+	files
+}
+
+fn read_files(paths: &[String], globs: Option<&str>, bytes_to_read: Option<u64>, memory_map: bool) -> (InputBytes, Vec<std::ops::Range<usize>>) {
+	// All the files back to back, `bytes_to_read` is shared by them
+	// A single file is loaded as is, so it can be mapped instead of copied
+	let files = list_files(paths, globs);
+	if let [file] = files.as_slice() {
+		let data = load_file(file, bytes_to_read, memory_map);
+		#[allow(clippy::single_range_in_vec_init)] // One file covering all the bytes
+		let file_bounds = vec![0..data.len()];
+		return (data, file_bounds);
+	}
+	let mut remaining = bytes_to_read.unwrap_or(u64::MAX);
+	let mut data = vec![];
+	let mut file_bounds = vec![];
+	for file in files {
+		if remaining == 0 {
+			break;
+		}
+		let start = data.len();
+		data.extend(read_file(&file, Some(remaining)));
+		remaining -= (data.len() - start) as u64;
+		file_bounds.push(start..data.len());
+	}
+	(data.into(), file_bounds)
 }
 
 trait ParseUInt: Sized {
@@ -129,10 +351,10 @@ fn parse_uint<T: ParseUInt + std::str::FromStr>(options: &Vec<&str>, starts_with
 	if let Some(br_str) = options.iter().find(|&s| s.starts_with(starts_with)) {
 		if let Some(br_value) = br_str.split("=").nth(1).and_then(|string| {
 			let s = string.replace("_", "");
-			if s.starts_with("0x") {
-				T::from_str_radix(&s[2..], 16).ok()
-			} else if s.starts_with("0b") {
-				T::from_str_radix(&s[2..], 2).ok()
+			if let Some(hex) = s.strip_prefix("0x") {
+				T::from_str_radix(hex, 16).ok()
+			} else if let Some(bin) = s.strip_prefix("0b") {
+				T::from_str_radix(bin, 2).ok()
 			} else {
 				s.parse::<T>().ok()
 			}
@@ -146,34 +368,285 @@ fn parse_uint<T: ParseUInt + std::str::FromStr>(options: &Vec<&str>, starts_with
 	None
 }
 
+fn parse_str(options: &Vec<&str>, starts_with: &str) -> Option<String> {
+	options.iter().find_map(|s| s.strip_prefix(starts_with)).map(|value| {
+		if value.is_empty() {
+			eprintln!("Error: Found \"{}\" but the value is empty", starts_with);
+			std::process::exit(1);
+		}
+		value.to_string()
+	})
+}
+
+fn parse_encode_mode(options: &Vec<&str>) -> tok_codec::EncodeMode {
+	match parse_uint::<u8>(options, "em=") {
+		Some(0) => tok_codec::EncodeMode::Greedy,
+		Some(1) => tok_codec::EncodeMode::MinTokens,
+		Some(2) | None => tok_codec::EncodeMode::MinBits,
+		Some(mode) => {
+			eprintln!("Error: Found \"em=\" but the mode {} is not 0, 1, or 2", mode);
+			std::process::exit(1);
+		}
+	}
+}
+
+fn parse_pruning_policy(options: &Vec<&str>) -> tok_trainer::PruningPolicy {
+	let number = parse_uint::<usize>(options, "ppn=");
+	if number == Some(0) {
+		eprintln!("Error: Found \"ppn=\" but it can't be 0");
+		std::process::exit(1);
+	}
+	let keys = number.unwrap_or(0x1_0000);
+	match parse_uint::<u8>(options, "pp=") {
+		Some(0) => tok_trainer::PruningPolicy::KeepAll,
+		Some(1) | None => tok_trainer::PruningPolicy::MinCount {
+			interval: number.unwrap_or(0xf_ffff),
+			min_count: parse_uint(options, "ppc=").unwrap_or(1),
+		},
+		Some(2) => tok_trainer::PruningPolicy::TopK { keys },
+		Some(3) => tok_trainer::PruningPolicy::SpaceSaving { keys },
+		Some(policy) => {
+			eprintln!("Error: Found \"pp=\" but the policy {} is not 0, 1, 2, or 3", policy);
+			std::process::exit(1);
+		}
+	}
+}
+
 fn process_cmd() {
 	let mut tok_parameters = TokenizerParameters {
 		dbg_lv: 0,
 		bin_dat: None,
+		file_bounds: None,
 		bytes_to_read: None,
+		memory_map: cfg!(feature = "mmap"),
 		trainer_chk_bytes: Some(16),
+		trainer_chk_overlap: None,
+		chunking_mode: tok_trainer::ChunkingMode::Fixed,
 		multi_threaded: None,
+		encode_mode: tok_codec::EncodeMode::MinBits,
+		output_path: None,
+		count_backend: tok_trainer::CountBackend::BTreeMap,
+		max_token_len: None,
+		vocab_size: None,
+		max_mem: None,
+		pruning_policy: tok_trainer::PruningPolicy::default(),
+		spill_runs: None,
+		pruning_metrics: tok_trainer::PruningMetrics::default(),
+		streaming: false,
+		trainer_mode: tok_trainer::TrainerMode::Greedy,
 	};
 	let parameters: Vec<String> = env::args().collect();
 	vaildate_parameters(&parameters);
 
-	let options = parameters[1].split(",").collect::<Vec<&str>>();
-	tok_parameters.bin_dat = read_file(&parameters[2], tok_parameters.bytes_to_read);
+	let options = parameters[2].split(",").collect::<Vec<&str>>();
 	tok_parameters.dbg_lv = parse_uint(&options, "v=").unwrap_or(debug_enum::SILENT);
 	tok_parameters.bytes_to_read = parse_uint(&options, "br=");
+	tok_parameters.memory_map = match parse_uint::<u8>(&options, "mm=") {
+		None => cfg!(feature = "mmap"),
+		Some(1) if !cfg!(feature = "mmap") => {
+			eprintln!("Error: Found \"mm=\" but this build can't map files");
+			eprintln!("Hint: Build it with \"--features mmap\"");
+			std::process::exit(1);
+		}
+		Some(1) => true,
+		Some(0) => false,
+		Some(mode) => {
+			eprintln!("Error: Found \"mm=\" but {} is not 0 or 1", mode);
+			std::process::exit(1);
+		}
+	};
 	tok_parameters.multi_threaded = parse_uint(&options, "mt=");
-	tok_parameters.trainer_chk_bytes = min(
-		parse_uint(&options, "tcb="),
-		Some(tok_parameters.bin_dat.clone().unwrap().len())
-	);
+	tok_parameters.encode_mode = parse_encode_mode(&options);
+	tok_parameters.output_path = parse_str(&options, "o=");
+	tok_parameters.max_token_len = parse_uint(&options, "ml=");
+	tok_parameters.vocab_size = parse_uint(&options, "vs=");
+	if tok_parameters.vocab_size.is_some_and(|vocab_size| vocab_size < tok_codec::FIRST_LEARNED_TOKEN as usize) {
+		eprintln!("Error: Found \"vs=\" but the vocabulary needs at least {} tokens", tok_codec::FIRST_LEARNED_TOKEN);
+		std::process::exit(1);
+	}
+	tok_parameters.max_mem = parse_uint(&options, "max_mem=");
+	if tok_parameters.max_mem == Some(0) {
+		eprintln!("Error: Found \"max_mem=\" but the budget can't be 0 bytes");
+		std::process::exit(1);
+	}
+	if tok_parameters.max_token_len == Some(0) {
+		eprintln!("Error: Found \"ml=\" but tokens can't be shorter than 1 byte");
+		std::process::exit(1);
+	}
+	tok_parameters.count_backend = match parse_uint::<u8>(&options, "cb=") {
+		Some(0) | None => tok_trainer::CountBackend::BTreeMap,
+		Some(1) => tok_trainer::CountBackend::SuffixArray,
+		Some(2) => {
+			// Parts per million, since the options are integers
+			let error = parse_uint::<u32>(&options, "cme=").unwrap_or(1000);
+			if !(1..=1_000_000).contains(&error) {
+				eprintln!("Error: Found \"cme=\" but {} is not 1 to 1000000 parts per million", error);
+				std::process::exit(1);
+			}
+			tok_trainer::CountBackend::CountMinSketch { error: error as f64 / 1_000_000.0 }
+		}
+		Some(backend) => {
+			eprintln!("Error: Found \"cb=\" but the backend {} is not 0, 1, or 2", backend);
+			std::process::exit(1);
+		}
+	};
+	tok_parameters.chunking_mode = match parse_uint::<u8>(&options, "tcm=") {
+		Some(0) | None => tok_trainer::ChunkingMode::Fixed,
+		Some(1) => tok_trainer::ChunkingMode::ContentDefined,
+		Some(mode) => {
+			eprintln!("Error: Found \"tcm=\" but the mode {} is not 0 or 1", mode);
+			std::process::exit(1);
+		}
+	};
+	tok_parameters.trainer_mode = match parse_uint::<u8>(&options, "tm=") {
+		Some(0) | None => tok_trainer::TrainerMode::Greedy,
+		Some(1) => tok_trainer::TrainerMode::BytePair,
+		Some(2) => tok_trainer::TrainerMode::Unigram,
+		Some(mode) => {
+			eprintln!("Error: Found \"tm=\" but the mode {} is not 0, 1, or 2", mode);
+			std::process::exit(1);
+		}
+	};
+	tok_parameters.pruning_policy = parse_pruning_policy(&options);
+	tok_parameters.streaming = match parse_uint::<u8>(&options, "st=") {
+		Some(0) | None => false,
+		Some(1) => true,
+		Some(mode) => {
+			eprintln!("Error: Found \"st=\" but {} is not 0 or 1", mode);
+			std::process::exit(1);
+		}
+	};
+	if tok_parameters.streaming && tok_parameters.trainer_mode != tok_trainer::TrainerMode::Greedy {
+		eprintln!("Error: Found \"st=1\" but only tm=0 can train while streaming");
+		std::process::exit(1);
+	}
+	if let Some(spill_dir) = parse_str(&options, "spill=") {
+		if tok_parameters.max_mem.is_none() || tok_parameters.trainer_mode != tok_trainer::TrainerMode::Greedy {
+			eprintln!("Error: Found \"spill=\" but it needs \"max_mem=\" and tm=0 to know when to spill");
+			std::process::exit(1);
+		}
+		tok_parameters.spill_runs = match tok_spill::SpillRuns::new(PathBuf::from(&spill_dir)) {
+			Ok(spill_runs) => Some(spill_runs),
+			Err(error) => {
+				eprintln!("Unable to create the spill directory {}: {}", spill_dir, error);
+				std::process::exit(1);
+			}
+		};
+	}
+	tok_parameters.trainer_chk_overlap = parse_uint(&options, "tco=");
+	if tok_parameters.trainer_chk_overlap.is_some_and(|overlap| overlap >= parse_uint(&options, "tcb=").unwrap_or(16)) {
+		eprintln!("Error: Found \"tco=\" but the overlap must be less than the chunk bytes");
+		std::process::exit(1);
+	}
 
 	if tok_parameters.has_verbose() { println!("Verbose: args: {:?}", parameters); }
 	if tok_parameters.has_verbose() { println!("Verbose: options: {:?}", options); }
 
-	tok_trainer::entry(&mut tok_parameters);
-	// tok_codec::demo();
+	let result = match parameters[1].as_str() {
+		"train" if tok_parameters.streaming => {
+			tok_parameters.trainer_chk_bytes = parse_uint(&options, "tcb=");
+			let files = list_files(&parameters[3..], parse_str(&options, "g=").as_deref());
+			if tok_parameters.has_info() { println!("Info: Streaming {} file(s)", files.len()); }
+			tok_trainer::stream_entry(&tok_parameters, files.iter().map(|file| open_file(file)))
+		}
+		"train" => {
+			let (bin_dat, file_bounds) = read_files(&parameters[3..], parse_str(&options, "g=").as_deref(), tok_parameters.bytes_to_read, tok_parameters.memory_map);
+			if tok_parameters.has_info() { println!("Info: Read {} file(s)", file_bounds.len()); }
+			tok_parameters.bin_dat = Some(bin_dat);
+			tok_parameters.file_bounds = Some(file_bounds);
+			tok_parameters.trainer_chk_bytes = min(
+				parse_uint(&options, "tcb="),
+				Some(tok_parameters.bin_dat.as_deref().unwrap().len())
+			);
+			tok_trainer::entry(&mut tok_parameters);
+			Ok(())
+		}
+		"encode" => {
+			tok_parameters.bin_dat = Some(load_file(Path::new(&parameters[4]), tok_parameters.bytes_to_read, tok_parameters.memory_map));
+			tok_codec::encode_entry(&tok_parameters, &parameters[3], &parameters[5])
+		}
+		"decode" => {
+			tok_parameters.bin_dat = Some(load_file(Path::new(&parameters[4]), tok_parameters.bytes_to_read, tok_parameters.memory_map));
+			tok_codec::decode_entry(&tok_parameters, &parameters[3], &parameters[5])
+		}
+		_ => unreachable!("vaildate_parameters() checks the commands"),
+	};
+	if let Err(error) = result {
+		eprintln!("Error: {}", error);
+		std::process::exit(1);
+	}
 }
 
 fn main() {
 	process_cmd();
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+
+	#[test]
+	fn test_glob_match() {
+		assert!(glob_match(b"*.svg", b"logo.svg"));
+		assert!(glob_match(b"*.svg", b".svg"));
+		assert!(!glob_match(b"*.svg", b"logo.svg.bak"));
+		assert!(glob_match(b"img_??.*", b"img_01.jpg"));
+		assert!(!glob_match(b"img_??.*", b"img_1.jpg"));
+		assert!(glob_match(b"*a*b*", b"xxaxxbxx"));
+		assert!(glob_match(b"*", b""));
+		assert!(!glob_match(b"", b"a"));
+	}
+
+	#[test]
+	fn test_read_files() {
+		let dir = env::temp_dir().join(format!("test_read_files_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("nested")).unwrap();
+		std::fs::write(dir.join("b.svg"), b"<svg/>").unwrap();
+		std::fs::write(dir.join("a.txt"), b"text").unwrap();
+		std::fs::write(dir.join("nested").join("c.svg"), b"<g/>").unwrap();
+		let dir_path = dir.to_str().unwrap().to_string();
+
+		let (data, file_bounds) = read_files(std::slice::from_ref(&dir_path), None, None, true);
+		assert_eq!(&*data, b"text<svg/><g/>");
+		assert_eq!(file_bounds, vec![0..4, 4..10, 10..14]);
+		let (data, file_bounds) = read_files(std::slice::from_ref(&dir_path), Some("*.svg"), None, true);
+		assert_eq!((&*data, file_bounds), (&b"<svg/><g/>"[..], vec![0..6, 6..10]));
+		// A file name that isn't UTF-8 is still read from the directory
+		#[cfg(unix)]
+		{
+			use std::os::unix::ffi::OsStrExt;
+			let non_utf8 = dir.join("nested").join(std::ffi::OsStr::from_bytes(b"x\xff.bin"));
+			std::fs::write(&non_utf8, b"!").unwrap();
+			let (data, file_bounds) = read_files(std::slice::from_ref(&dir_path), None, None, true);
+			assert_eq!((&*data, file_bounds.len()), (&b"text<svg/><g/>!"[..], 4));
+			std::fs::remove_file(non_utf8).unwrap();
+		}
+		let (data, file_bounds) = read_files(&[dir_path, dir.join("a.txt").to_str().unwrap().to_string()], Some("*.svg|*.jpg"), Some(8), true);
+		assert_eq!((&*data, file_bounds), (&b"<svg/><g"[..], vec![0..6, 6..8]));
+		// A single file is mapped, or read with "mm=0"
+		let file_path = dir.join("b.svg").to_str().unwrap().to_string();
+		for memory_map in [true, false] {
+			let (data, file_bounds) = read_files(std::slice::from_ref(&file_path), None, Some(4), memory_map);
+			#[cfg(feature = "mmap")]
+			assert_eq!(matches!(data, InputBytes::Mapped(_)), memory_map);
+			assert_eq!((&*data, file_bounds.len(), file_bounds[0].clone()), (&b"<svg"[..], 1, 0..4));
+		}
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_collect_files_symlinks() {
+		let dir = env::temp_dir().join(format!("test_collect_files_symlinks_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("nested")).unwrap();
+		std::fs::write(dir.join("a.svg"), b"<svg/>").unwrap();
+		// A loop back to the top, a second way into "nested", and a linked file that is still read
+		std::os::unix::fs::symlink(&dir, dir.join("nested").join("loop")).unwrap();
+		std::os::unix::fs::symlink(dir.join("nested"), dir.join("same")).unwrap();
+		std::os::unix::fs::symlink(dir.join("a.svg"), dir.join("nested").join("b.svg")).unwrap();
+		let mut files = vec![];
+		collect_files(&dir, &[], &mut files);
+		assert_eq!(files, vec![dir.join("a.svg"), dir.join("nested").join("b.svg")]);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,202 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::File;
+use std::io::Write;
+use crate::{TokenizerParameters, tok_codec};
+
+
+// Marks the end of a bound in `next` and `previous`, and a merged away symbol in `symbols`
+const NONE: usize = usize::MAX;
+const REMOVED: u32 = u32::MAX;
+
+#[derive(Debug, PartialEq)]
+pub struct Merge {
+	pub left: Vec<u8>,
+	pub right: Vec<u8>,
+	// How many times the pair was seen when it was merged
+	pub count: usize,
+}
+
+struct PairCounter {
+	counts: BTreeMap<(u32, u32), usize>,
+	// Where the pairs might be; stale positions are skipped when merging
+	positions: BTreeMap<(u32, u32), Vec<usize>>,
+	// Lazy max heap, an entry is only valid if it matches `counts`
+	heap: BinaryHeap<(usize, Reverse<(u32, u32)>)>,
+}
+
+impl PairCounter {
+	fn increment(&mut self, pair: (u32, u32), position: usize) {
+		let count = self.counts.entry(pair).or_insert(0);
+		*count += 1;
+		self.heap.push((*count, Reverse(pair)));
+		self.positions.entry(pair).or_default().push(position);
+	}
+
+	fn decrement(&mut self, pair: (u32, u32)) {
+		if let Some(count) = self.counts.get_mut(&pair) {
+			*count -= 1;
+			if *count == 0 {
+				self.counts.remove(&pair);
+			}
+		}
+	}
+
+	fn pop_most_frequent(&mut self) -> Option<((u32, u32), usize)> {
+		// Ties go to the smallest pair, so the merges don't depend on the order the pairs were counted in
+		while let Some((count, Reverse(pair))) = self.heap.pop() {
+			match self.counts.get(&pair) {
+				Some(&current) if current == count => return Some((pair, count)),
+				// The count went down since; the real count is put back
+				Some(&current) => self.heap.push((current, Reverse(pair))),
+				None => {}
+			}
+		}
+		None
+	}
+}
+
+pub fn train_byte_pair(param: &TokenizerParameters) -> Vec<Merge> {
+	// Classic BPE: merge the most frequent adjacent pair across the whole data until no pair repeats
+	// The pairs are counted once, then only the neighbors of each merge are updated
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	let max_token_len = param.max_token_len.unwrap_or(usize::MAX);
+	let max_merges = param.vocab_size.map_or(usize::MAX, |vocab_size| vocab_size.saturating_sub(tok_codec::FIRST_LEARNED_TOKEN as usize));
+
+	let mut symbols: Vec<u32> = bin_dat.iter().map(|&byte| byte as u32).collect();
+	// Linked per file, so no pair spans two files
+	let mut previous = vec![NONE; symbols.len()];
+	let mut next = vec![NONE; symbols.len()];
+	for file in param.file_bounds() {
+		for position in file.start + 1..file.end {
+			previous[position] = position - 1;
+			next[position - 1] = position;
+		}
+	}
+	let mut pieces: Vec<Vec<u8>> = (0..=u8::MAX).map(|byte| vec![byte]).collect();
+
+	let mut pairs = PairCounter { counts: BTreeMap::new(), positions: BTreeMap::new(), heap: BinaryHeap::new() };
+	for position in 0..symbols.len() {
+		if next[position] != NONE {
+			pairs.increment((symbols[position], symbols[next[position]]), position);
+		}
+	}
+
+	let mut merges = vec![];
+	while merges.len() < max_merges {
+		let Some(((left, right), count)) = pairs.pop_most_frequent() else { break };
+		if count < 2 {
+			break;
+		}
+		pairs.counts.remove(&(left, right));
+		let positions = pairs.positions.remove(&(left, right)).unwrap_or_default();
+		if pieces[left as usize].len() + pieces[right as usize].len() > max_token_len {
+			// It'll never be merged, even if it's counted again later
+			continue;
+		}
+
+		let merged = pieces.len() as u32;
+		pieces.push([pieces[left as usize].as_slice(), &pieces[right as usize]].concat());
+		merges.push(Merge { left: pieces[left as usize].clone(), right: pieces[right as usize].clone(), count });
+		if param.has_verbose() { println!("Verbose: Merge {}: {:?} + {:?} * {}", merges.len(), pieces[left as usize], pieces[right as usize], count); }
+
+		for position in positions {
+			let right_position = next[position];
+			if symbols[position] != left || right_position == NONE || symbols[right_position] != right {
+				continue; // Overlapped with another merge like "aaa"
+			}
+			let (before, after) = (previous[position], next[right_position]);
+			if before != NONE && (symbols[before], left) != (left, right) {
+				pairs.decrement((symbols[before], left));
+			}
+			if after != NONE && (right, symbols[after]) != (left, right) {
+				pairs.decrement((right, symbols[after]));
+			}
+
+			symbols[position] = merged;
+			symbols[right_position] = REMOVED;
+			next[position] = after;
+			if after != NONE {
+				previous[after] = position;
+				pairs.increment((merged, symbols[after]), position);
+			}
+			if before != NONE {
+				pairs.increment((symbols[before], merged), before);
+			}
+		}
+	}
+	merges
+}
+
+pub fn merges_to_model(merges: &[Merge]) -> BTreeMap<Vec<u8>, i32> {
+	// Scored like the greedy trainer: `count * sub.len() - count * byte_size - sub.len()`
+	let mut tokenizer_model = BTreeMap::new();
+	for merge in merges {
+		let token = [merge.left.as_slice(), &merge.right].concat();
+		let len = token.len() as i64;
+		let score = (merge.count as i64).saturating_mul(len - 1) - len;
+		// The same bytes can come from other pairs later, but with fewer counts
+		tokenizer_model.entry(token).or_insert(score.clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+	}
+	tokenizer_model
+}
+
+pub fn merges_path(vocab_path: &str) -> String {
+	// Example: "output.vocab.txt" -> "output.merges.txt"
+	match vocab_path.strip_suffix(".vocab.txt") {
+		Some(stem) => format!("{}.merges.txt", stem),
+		None => format!("{}.merges.txt", vocab_path),
+	}
+}
+
+pub fn write_merges(file_path: &str, merges: &[Merge]) {
+	// Example: "0\t[0]\t[0]\t1234" is the first merge
+	let mut file = File::create(file_path).expect("create failed");
+	for (rank, merge) in merges.iter().enumerate() {
+		writeln!(file, "{}\t{:?}\t{:?}\t{}", rank, merge.left, merge.right, merge.count).expect("write failed");
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{tok_byte_pair::*, TokenizerParameters};
+
+	fn merge(left: &[u8], right: &[u8], count: usize) -> Merge {
+		Merge { left: left.to_vec(), right: right.to_vec(), count }
+	}
+
+	#[test]
+	fn test_train_byte_pair() {
+		let mut param = TokenizerParameters { bin_dat: Some(b"abababcabc".to_vec().into()), ..Default::default() };
+		// ab * 4, then [ab, ab] and [ab, c] are tied at 2 so the smaller pair goes first
+		assert_eq!(train_byte_pair(&param), vec![merge(b"a", b"b", 4), merge(b"ab", b"c", 2)]);
+
+		// [ab, c] and [ab, ab] are too long, so it stops there
+		param.max_token_len = Some(2);
+		assert_eq!(train_byte_pair(&param), vec![merge(b"a", b"b", 4)]);
+
+		param.max_token_len = None;
+		param.vocab_size = Some(tok_codec::FIRST_LEARNED_TOKEN as usize + 1);
+		assert_eq!(train_byte_pair(&param), vec![merge(b"a", b"b", 4)]);
+
+		// Overlapping pairs only merge once
+		let param = TokenizerParameters { bin_dat: Some(b"aaaaa".to_vec().into()), ..Default::default() };
+		assert_eq!(train_byte_pair(&param), vec![merge(b"a", b"a", 4)]);
+
+		// The second "ab" spans two files
+		let mut param = TokenizerParameters { bin_dat: Some(b"abab".to_vec().into()), ..Default::default() };
+		assert_eq!(train_byte_pair(&param), vec![merge(b"a", b"b", 2)]);
+		param.file_bounds = Some(vec![0..1, 1..4]);
+		assert_eq!(train_byte_pair(&param), vec![]);
+	}
+
+	#[test]
+	fn test_merges_to_model() {
+		let model = merges_to_model(&[merge(b"a", b"b", 4), merge(b"ab", b"c", 2), merge(b"a", b"bc", 1)]);
+		assert_eq!(model.len(), 2);
+		assert_eq!(model[&b"ab".to_vec()], 4 - 2);
+		assert_eq!(model[&b"abc".to_vec()], 2 * 2 - 3);
+		assert_eq!(merges_path("output.vocab.txt"), "output.merges.txt");
+		assert_eq!(merges_path("model"), "model.merges.txt");
+	}
+}
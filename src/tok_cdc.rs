@@ -0,0 +1,99 @@
+// Content-defined chunking with a Gear rolling hash, like FastCDC
+// The cuts only depend on the bytes right before them, so the same bytes get cut the same way
+// wherever they are in the file, unlike the fixed size chunks
+use std::cmp::min;
+use std::ops::Range;
+
+
+// SplitMix64 of the index, a fixed table made at compile time rather than a random one,
+// so the same bytes are always cut in the same places, in any file and on any run
+const GEAR: [u64; 256] = {
+	let mut table = [0; 256];
+	let mut state: u64 = 0;
+	let mut index = 0;
+	while index < 256 {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[index] = z ^ (z >> 31);
+		index += 1;
+	}
+	table
+};
+
+fn top_bits(count: u32) -> u64 {
+	// The high bits of a Gear hash depend on the last 64 bytes, the low ones only on the last few
+	match count {
+		0 => 0,
+		count => !0u64 << (64 - count.min(64)),
+	}
+}
+
+fn cut_point(data: &[u8], min_size: usize, average_size: usize, mask_small: u64, mask_large: u64) -> usize {
+	// Normalized chunking: harder to cut before the average size and easier after,
+	// so the sizes cluster around the average
+	let mut hash = 0u64;
+	for (index, &byte) in data.iter().enumerate().skip(min_size) {
+		hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+		let mask = if index < average_size { mask_small } else { mask_large };
+		if hash & mask == 0 {
+			return index + 1;
+		}
+	}
+	data.len()
+}
+
+pub fn content_defined_chunks(data: &[u8], average_size: usize) -> Vec<Range<usize>> {
+	// The chunks are a quarter to twice the average size, the last one can be shorter
+	// Twice at most because the greedy trainer takes O(n ** 2) memory in the chunk size
+	let average_size = average_size.max(1);
+	let min_size = (average_size / 4).max(1);
+	let max_size = average_size * 2;
+	let bits = average_size.ilog2();
+	let (mask_small, mask_large) = (top_bits(bits + 1), top_bits(bits.saturating_sub(1)));
+
+	let mut chunks = vec![];
+	let mut start = 0;
+	while start < data.len() {
+		let window = &data[start..min(start + max_size, data.len())];
+		let end = start + cut_point(window, min_size, average_size, mask_small, mask_large);
+		chunks.push(start..end);
+		start = end;
+	}
+	chunks
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::tok_cdc::*;
+
+	fn test_data(len: usize) -> Vec<u8> {
+		(0..len).map(|index| (GEAR[index % 256] >> (index / 256 % 57)) as u8).collect()
+	}
+
+	#[test]
+	fn test_content_defined_chunks() {
+		let data = test_data(8192);
+		let chunks = content_defined_chunks(&data, 64);
+		assert_eq!(chunks.first().unwrap().start, 0);
+		assert_eq!(chunks.last().unwrap().end, data.len());
+		assert!(chunks.windows(2).all(|pair| pair[0].end == pair[1].start));
+		assert!(chunks[..chunks.len() - 1].iter().all(|chunk| (16..=128).contains(&chunk.len())));
+		assert!((8192 / 128..=8192 / 16).contains(&chunks.len()));
+
+		assert_eq!(content_defined_chunks(&[], 64), vec![]);
+		assert!(content_defined_chunks(&[1, 2, 3], 0).iter().all(|chunk| chunk.len() <= 2));
+	}
+
+	#[test]
+	fn test_shifted_cuts() {
+		// Inserting bytes at the front leaves most of the cuts where they were
+		let data = test_data(8192);
+		let shifted: Vec<u8> = b"shifted".iter().chain(&data).copied().collect();
+		let cuts: Vec<usize> = content_defined_chunks(&data, 64).into_iter().map(|chunk| chunk.end).collect();
+		let shifted_cuts: Vec<usize> = content_defined_chunks(&shifted, 64).into_iter().map(|chunk| chunk.end - 7).collect();
+		let common = cuts.iter().filter(|cut| shifted_cuts.contains(cut)).count();
+		assert!(common >= cuts.len() * 9 / 10, "{} of {} cuts", common, cuts.len());
+	}
+}
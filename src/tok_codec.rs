@@ -1,67 +1,694 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Write};
 use std::str::FromStr;
+use crate::{TokenizerParameters, tok_entropy::HuffmanCode};
 
 
-const DEMO_STRING: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+// Token IDs below this are single bytes, so any input can be encoded even with an empty vocabulary
+pub const BYTE_FALLBACK_TOKENS: u32 = 256;
+// Reserved right after the fallback bytes; they decode to nothing
+pub const SPECIAL_TOKENS: [&str; 3] = ["<pad>", "<bos>", "<eos>"];
+pub const FIRST_LEARNED_TOKEN: u32 = BYTE_FALLBACK_TOKENS + SPECIAL_TOKENS.len() as u32;
+
+// Compressed file layout, little-endian:
+// magic (4) | version (1) | original length (8) | vocab fingerprint (8) | token count (8)
+// | Huffman coded token IDs | CRC-32 of everything before it (4)
+pub const CONTAINER_MAGIC: &[u8; 4] = b"BYPC";
+pub const CONTAINER_VERSION: u8 = 2;
+const CONTAINER_HEADER_BYTES: usize = 4 + 1 + 8 + 8 + 8;
+
+// The Viterbi keeps at most this many positions when the best paths don't agree on a boundary,
+// e.g. a long run of one byte with equal cost tokens; it cuts there and the result can differ from the whole input
+const VITERBI_WINDOW: usize = 1 << 16;
+
+// Log-probabilities are turned into Huffman frequencies with this many steps per probability 1
+const LOG_PROB_SCALE: f64 = (1u64 << 32) as f64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EncodeMode {
+	// Takes the longest token at each position
+	Greedy,
+	// Viterbi over the input to find the fewest tokens
+	MinTokens,
+	// Viterbi over the input with the token costs estimated from the scores
+	#[default]
+	MinBits,
+}
 
 #[derive(Debug)]
 struct TokCodec {
-	model: BTreeMap<Vec<u8>, i32>
+	// Indexed by token ID, special tokens have no bytes
+	pieces: Vec<Vec<u8>>,
+	// Estimated from the scores for `EncodeMode::MinBits`
+	token_bits: Vec<f64>,
+	// Built once at load time, so matching at a position is O(longest token)
+	trie: ByteTrie,
+	// Of the tokens in `trie`, so the Viterbi knows how far back a token can still start
+	max_token_len: usize,
+	// Stored in the compressed files to catch decoding with a different vocabulary
+	fingerprint: u64,
+	// Built from `token_frequencies()`, so it's never stored in the compressed files
+	huffman: HuffmanCode,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+	// Sorted by the byte for binary search; most nodes only have a few children
+	children: Vec<(u8, u32)>,
+	token_id: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct ByteTrie {
+	nodes: Vec<TrieNode>,
+}
+
+impl ByteTrie {
+	pub fn new() -> Self {
+		ByteTrie { nodes: vec![TrieNode::default()] }
+	}
+
+	fn child(&self, node: u32, byte: u8) -> Option<u32> {
+		let children = &self.nodes[node as usize].children;
+		children.binary_search_by_key(&byte, |&(b, _)| b).ok().map(|index| children[index].1)
+	}
+
+	// Returns the previous token ID of the bytes, if any
+	pub fn insert(&mut self, bytes: &[u8], token_id: u32) -> Option<u32> {
+		let mut node = 0;
+		for &byte in bytes {
+			node = match self.child(node, byte) {
+				Some(child) => child,
+				None => {
+					let child = self.nodes.len() as u32;
+					self.nodes.push(TrieNode::default());
+					let children = &mut self.nodes[node as usize].children;
+					let index = children.partition_point(|&(b, _)| b < byte);
+					children.insert(index, (byte, child));
+					child
+				}
+			};
+		}
+		self.nodes[node as usize].token_id.replace(token_id)
+	}
+
+	// Every token that is a prefix of the bytes as (length, token ID), from the shortest
+	pub fn prefixes<'a>(&'a self, bytes: &'a [u8]) -> impl Iterator<Item = (usize, u32)> + 'a {
+		let mut node = Some(0);
+		bytes.iter().enumerate().map_while(move |(index, &byte)| {
+			node = self.child(node?, byte);
+			node.map(|node| (index + 1, self.nodes[node as usize].token_id))
+		}).filter_map(|(len, token_id)| token_id.map(|id| (len, id)))
+	}
+}
+
+pub fn assign_token_ids<S: Copy + Default + PartialOrd>(model: &BTreeMap<Vec<u8>, S>) -> Vec<(Vec<u8>, S)> {
+	// The IDs are stable as long as the model is the same:
+	// The fallback bytes, the special tokens, then the learned tokens from the highest score
+	// Ties are broken by the bytes because the sort is stable over the sorted `model`
+	// The scores are the trainer's `i32` scores, or the `f64` log-probabilities of the unigram model
+	let mut learned: Vec<(&Vec<u8>, &S)> = model.iter().filter(|(bytes, _)| bytes.len() > 1).collect();
+	learned.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+	(0..=u8::MAX).map(|byte| (vec![byte], model.get(&vec![byte]).copied().unwrap_or_default()))
+		.chain(SPECIAL_TOKENS.iter().map(|_| (vec![], S::default())))
+		.chain(learned.into_iter().map(|(bytes, &score)| (bytes.clone(), score)))
+		.collect()
+}
+
+pub fn write_vocab<S: Copy + Default + PartialOrd + Debug>(file_path: &str, model: &BTreeMap<Vec<u8>, S>) {
+	// Example: "259\t[0, 0, 0, 0, 0, 0, 0, 0, 0]\t567"
+	// Or "259\t[0, 0]\t-3.25" with log-probabilities; `Debug` always writes the decimal point of a `f64`,
+	// that's how `TokCodec::new()` tells them apart
+	let mut file = File::create(file_path).expect("create failed");
+	for (id, (bytes, score)) in assign_token_ids(model).iter().enumerate() {
+		let id = id as u32;
+		if (BYTE_FALLBACK_TOKENS..FIRST_LEARNED_TOKEN).contains(&id) {
+			writeln!(file, "{}\t{}\t{:?}", id, SPECIAL_TOKENS[(id - BYTE_FALLBACK_TOKENS) as usize], score).expect("write failed");
+		} else {
+			writeln!(file, "{}\t{:?}\t{:?}", id, bytes, score).expect("write failed");
+		}
+	}
+}
+
+pub fn token_frequencies(pieces: &[Vec<u8>], scores: &[i32]) -> Vec<u64> {
+	// The trainer scores the fallback bytes with their counts,
+	// and the rest with `count * len - count - len` bytes saved, so the count is solved back from it
+	// Every token gets at least 1 so nothing is impossible to encode
+	pieces.iter().zip(scores).map(|(piece, &score)| {
+		let score = score.max(0) as u64;
+		match piece.len() {
+			0 => 1,
+			1 => score + 1,
+			len => (score + len as u64) / (len as u64 - 1) + 1,
+		}
+	}).collect()
+}
+
+pub fn log_prob_frequencies(pieces: &[Vec<u8>], log_probs: &[f64]) -> Vec<u64> {
+	// The unigram model already has the probabilities, the special tokens still get 1
+	pieces.iter().zip(log_probs).map(|(piece, &log_prob)| match piece.len() {
+		0 => 1,
+		_ => (log_prob.min(0.0).exp() * LOG_PROB_SCALE) as u64 + 1,
+	}).collect()
+}
+
+fn agreed_boundary(best: &[(f64, u32, usize)], settled: usize, done: usize, max_token_len: usize) -> usize {
+	// The best path over the whole input leaves `done` from one of these positions, a token is no longer;
+	// walking back the furthest one until they meet gives the boundary all of them go through
+	let mut ends: BTreeSet<usize> = (done.saturating_sub(max_token_len.max(1) - 1).max(settled)..=done)
+		.filter(|&end| best[end - settled].0.is_finite())
+		.collect();
+	while ends.len() > 1 {
+		let end = ends.pop_last().unwrap();
+		ends.insert(end - best[end - settled].2);
+	}
+	ends.pop_last().unwrap_or(settled)
+}
+
+fn push_best_path(best: &[(f64, u32, usize)], settled: usize, to: usize, tokens: &mut Vec<u32>) {
+	// Walk back from `to`; the fallback bytes make sure every position is reachable
+	let first = tokens.len();
+	let mut end = to;
+	while end > settled {
+		let (_, id, len) = best[end - settled];
+		tokens.push(id);
+		end -= len;
+	}
+	tokens[first..].reverse();
+}
+
+fn token_bits(frequencies: &[u64]) -> Vec<f64> {
+	let total = (frequencies.iter().sum::<u64>() as f64).log2();
+	frequencies.iter().map(|&frequency| total - (frequency as f64).log2()).collect()
+}
+
+fn vocab_fingerprint<const N: usize>(pieces: &[Vec<u8>], scores: impl Iterator<Item = [u8; N]>) -> u64 {
+	// FNV-1a over the pieces and scores in ID order; the lengths are hashed too so [1, 2] + [3] != [1] + [2, 3]
+	// The scores are little-endian, 4 bytes for the `i32` scores and 8 for the `f64` log-probabilities
+	let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+	for (piece, score) in pieces.iter().zip(scores) {
+		let fields = [&(piece.len() as u32).to_le_bytes()[..], piece, &score];
+		for &byte in fields.iter().flat_map(|field| field.iter()) {
+			hash ^= byte as u64;
+			hash = hash.wrapping_mul(0x100_0000_01b3);
+		}
+	}
+	hash
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+	// The usual CRC-32 (IEEE 802.3), bit by bit because it only runs once per file
+	let mut crc = !0u32;
+	for &byte in bytes {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
+
+fn parse_bytes(vector_part: &str) -> Result<Vec<u8>, String> {
+	// Example: "[0, 0]"
+	let inner = vector_part.strip_prefix('[').and_then(|s| s.strip_suffix(']'))
+		.ok_or(format!("Expected bytes in square brackets, found {:?}", vector_part))?;
+	inner.split(',')
+		.map(|s| u8::from_str(s.trim()).map_err(|error| format!("Invalid byte {:?}: {}", s, error)))
+		.collect() // Example: [0, 0]
 }
 
 
 impl TokCodec {
-	fn new(file_path: &str) -> TokCodec {
-		let file = File::open(file_path).unwrap();
+	fn new(file_path: &str) -> Result<TokCodec, String> {
+		let file = File::open(file_path).map_err(|error| format!("Unable to open {}: {}", file_path, error))?;
 		let reader = BufReader::new(file);
-		let mut model = BTreeMap::new();
+		let mut vocab = vec![];
+		let mut legacy_model = BTreeMap::new();
+		let mut has_log_probs = false;
+
+		for (line_index, line) in reader.lines().enumerate() {
+			let line = line.map_err(|error| format!("Unable to read {}: {}", file_path, error))?;
+			let at_line = |error: String| format!("{}:{}: {}", file_path, line_index + 1, error);
+			let parts: Vec<&str> = line.split('\t').collect(); // Example: ["259", "[0, 0]", "40"]
 
-		for line in reader.lines() {
-			let line = line.unwrap();
-			let parts: Vec<&str> = line.split('\t').collect(); // Example: ["[0, 0]", "40"]
+			match parts[..] {
+				[id, piece, score] => {
+					let id = u32::from_str(id).map_err(|error| at_line(format!("Invalid token ID: {}", error)))?;
+					// Any score that isn't an integer makes them all log-probabilities
+					has_log_probs |= i32::from_str(score).is_err();
+					let score = f64::from_str(score).map_err(|error| at_line(format!("Invalid score: {}", error)))?;
+					if (BYTE_FALLBACK_TOKENS..FIRST_LEARNED_TOKEN).contains(&id) {
+						let expected = SPECIAL_TOKENS[(id - BYTE_FALLBACK_TOKENS) as usize];
+						if piece != expected {
+							return Err(at_line(format!("Token ID {} is reserved for {}, found {}", id, expected, piece)));
+						}
+						vocab.push((id, vec![], score));
+					} else {
+						vocab.push((id, parse_bytes(piece).map_err(at_line)?, score));
+					}
+				}
+				// Vocabularies written before the IDs were persisted
+				[piece, score] => {
+					let score = i32::from_str(score).map_err(|error| at_line(format!("Invalid score: {}", error)))?;
+					legacy_model.insert(parse_bytes(piece).map_err(at_line)?, score);
+				}
+				_ => return Err(at_line(format!("Expected 3 tab separated columns, found {}", parts.len()))),
+			}
+		}
 
-			// Parse the vector part
-			// Example: "[0, 0]"
-			let vector_part = &parts[0][1..parts[0].len()-1]; // Remove the square brackets
-			let vector_elements: Vec<u8> = vector_part.split(',')
-				.map(|s| u8::from_str(s.trim()).unwrap())
-				.collect(); // Example: [0, 0]
+		if !legacy_model.is_empty() {
+			if !vocab.is_empty() {
+				return Err(format!("{}: Mixed vocabulary formats", file_path));
+			}
+			return Ok(TokCodec::from_model(&legacy_model));
+		}
+		vocab.sort_by_key(|(id, _, _)| *id);
+		for (index, (id, bytes, _)) in vocab.iter().enumerate() {
+			if *id != index as u32 {
+				return Err(format!("{}: Token IDs must be contiguous from 0; expected {}, found {}", file_path, index, id));
+			}
+			if *id < BYTE_FALLBACK_TOKENS && *bytes != [*id as u8] {
+				return Err(format!("{}: Token ID {} is reserved for byte {}, found {:?}", file_path, id, id, bytes));
+			}
+		}
+		if vocab.len() < FIRST_LEARNED_TOKEN as usize {
+			return Err(format!("{}: Missing the {} reserved tokens", file_path, FIRST_LEARNED_TOKEN));
+		}
+		let pieces = vocab.into_iter().map(|(_, bytes, score)| (bytes, score));
+		if has_log_probs {
+			TokCodec::from_log_probs(pieces.collect())
+		} else {
+			TokCodec::from_pieces(pieces.map(|(bytes, score)| (bytes, score as i32)).collect())
+		}.map_err(|error| format!("{}: {}", file_path, error))
+	}
 
-			// Parse the integer part
-			let integer_part = i32::from_str(parts[1]).unwrap(); // Example: 40
+	fn from_model(model: &BTreeMap<Vec<u8>, i32>) -> TokCodec {
+		TokCodec::from_pieces(assign_token_ids(model)).unwrap()
+	}
+
+	fn from_pieces(vocab: Vec<(Vec<u8>, i32)>) -> Result<TokCodec, String> {
+		let (pieces, scores): (Vec<Vec<u8>>, Vec<i32>) = vocab.into_iter().unzip();
+		let frequencies = token_frequencies(&pieces, &scores);
+		let fingerprint = vocab_fingerprint(&pieces, scores.iter().map(|score| score.to_le_bytes()));
+		TokCodec::from_frequencies(pieces, &frequencies, fingerprint)
+	}
 
-			// Insert into the BTreeMap
-			model.insert(vector_elements, integer_part);
+	fn from_log_probs(vocab: Vec<(Vec<u8>, f64)>) -> Result<TokCodec, String> {
+		let (pieces, log_probs): (Vec<Vec<u8>>, Vec<f64>) = vocab.into_iter().unzip();
+		let frequencies = log_prob_frequencies(&pieces, &log_probs);
+		let fingerprint = vocab_fingerprint(&pieces, log_probs.iter().map(|log_prob| log_prob.to_le_bytes()));
+		TokCodec::from_frequencies(pieces, &frequencies, fingerprint)
+	}
+
+	fn from_frequencies(pieces: Vec<Vec<u8>>, frequencies: &[u64], fingerprint: u64) -> Result<TokCodec, String> {
+		let mut trie = ByteTrie::new();
+		for (id, bytes) in pieces.iter().enumerate() {
+			if !bytes.is_empty() && trie.insert(bytes, id as u32).is_some() {
+				return Err(format!("Duplicated token {:?}", bytes));
+			}
 		}
+		let max_token_len = pieces.iter().map(Vec::len).max().unwrap_or(0);
+		let token_bits = token_bits(frequencies);
+		let huffman = HuffmanCode::new(frequencies);
+		Ok(TokCodec { pieces, token_bits, trie, max_token_len, fingerprint, huffman })
+	}
 
-		TokCodec { model }
+	fn limit_token_len(&mut self, max_token_len: usize) {
+		// Longer tokens are only left out of the matching; they still decode,
+		// and their Huffman codes stay because the decoder doesn't know the limit
+		let mut trie = ByteTrie::new();
+		self.max_token_len = 0;
+		for (id, bytes) in self.pieces.iter().enumerate() {
+			if !bytes.is_empty() && bytes.len() <= max_token_len.max(1) {
+				trie.insert(bytes, id as u32);
+				self.max_token_len = self.max_token_len.max(bytes.len());
+			}
+		}
+		self.trie = trie;
 	}
 
-	fn from_longest_bytes_iter(&self) -> impl Iterator<Item = (Vec<u8>, i32)> + '_ {
-		let mut mapped: Vec<Vec<u8>> = self.model.iter().map(|(bytes, _)| bytes.clone()).collect();
-		mapped.sort_by_key(|bytes| bytes.len());
-		mapped.into_iter().rev().map(move |bytes| (bytes.clone(), self.model[&bytes]))
+	fn longest_match(&self, bytes: &[u8]) -> Option<(usize, u32)> {
+		self.trie.prefixes(bytes).last()
 	}
 
-	fn encode(&self, bytes: &[u8]) {
-		// Start with the longest string
-		for (bytes, freq) in self.from_longest_bytes_iter() {
-			println!("Bytes: {:?}, Length: {}, Frequency: {}", bytes, bytes.len(), freq);
+	fn encode(&self, bytes: &[u8], mode: EncodeMode) -> Vec<u32> {
+		match mode {
+			EncodeMode::Greedy => self.encode_greedy(bytes),
+			EncodeMode::MinTokens => self.encode_viterbi(bytes, |_| 1.0),
+			EncodeMode::MinBits => self.encode_viterbi(bytes, |id| self.token_bits[id as usize]),
 		}
-		unimplemented!()
 	}
 
-	fn decode(&self) {
-		unimplemented!()
+	fn encode_viterbi(&self, bytes: &[u8], token_cost: impl Fn(u32) -> f64) -> Vec<u32> {
+		self.encode_viterbi_windowed(bytes, token_cost, VITERBI_WINDOW)
+	}
+
+	fn encode_viterbi_windowed(&self, bytes: &[u8], token_cost: impl Fn(u32) -> f64, window: usize) -> Vec<u32> {
+		// `best[i - settled]` is the cheapest (cost, token ID, token length) that ends at byte `i`;
+		// the tokens before `settled` are already out, so it's O(window) memory instead of 24 bytes per input byte
+		let mut best = vec![(0.0, 0, 0)];
+		let mut settled = 0;
+		let mut tokens = vec![];
+		let min_check = 2 * self.max_token_len.max(1);
+		let mut check = min_check;
+		for start in 0..bytes.len() {
+			let cost_so_far = best[start - settled].0;
+			for (len, id) in self.trie.prefixes(&bytes[start..]) {
+				let end = start + len - settled;
+				if best.len() <= end {
+					best.resize(end + 1, (f64::INFINITY, 0, 0));
+				}
+				let cost = cost_so_far + token_cost(id);
+				if cost < best[end].0 {
+					best[end] = (cost, id, len);
+				}
+			}
+
+			// Everything up to `done` is final, the costs stay absolute so it's the same sums as the whole input
+			let done = start + 1;
+			if done - settled < check {
+				continue;
+			}
+			let boundary = agreed_boundary(&best, settled, done, self.max_token_len);
+			if boundary > settled {
+				push_best_path(&best, settled, boundary, &mut tokens);
+				best.drain(..boundary - settled);
+				settled = boundary;
+				check = min_check;
+			} else if done - settled >= window {
+				// The ones after `done` may come from before it, they're found again from `done`
+				push_best_path(&best, settled, done, &mut tokens);
+				best.truncate(done - settled + 1);
+				best.drain(..done - settled);
+				settled = done;
+				check = min_check;
+			} else {
+				// Back off, so the paths that won't agree aren't walked at every position
+				check *= 2;
+			}
+		}
+		push_best_path(&best, settled, bytes.len(), &mut tokens);
+		tokens
 	}
+
+	fn encode_greedy(&self, bytes: &[u8]) -> Vec<u32> {
+		// Greedy longest match; it won't backtrack if a shorter token leads to fewer tokens
+		let mut tokens = vec![];
+		let mut index = 0;
+		while index < bytes.len() {
+			// The fallback bytes always match
+			let (len, id) = self.longest_match(&bytes[index..]).unwrap();
+			tokens.push(id);
+			index += len;
+		}
+		tokens
+	}
+
+	fn decode(&self, tokens: &[u32]) -> Result<Vec<u8>, String> {
+		let mut bytes = vec![];
+		for (index, &id) in tokens.iter().enumerate() {
+			match self.pieces.get(id as usize) {
+				Some(piece) => bytes.extend_from_slice(piece),
+				None => return Err(format!("Unknown token ID {} at index {}", id, index)),
+			}
+		}
+		Ok(bytes)
+	}
+
+	fn write_container(&self, bytes: &[u8], mode: EncodeMode, writer: &mut impl Write) -> io::Result<()> {
+		let tokens = self.encode(bytes, mode);
+		let token_stream = self.huffman.encode(&tokens);
+		let mut output = Vec::with_capacity(CONTAINER_HEADER_BYTES + token_stream.len() + 4);
+		output.extend_from_slice(CONTAINER_MAGIC);
+		output.push(CONTAINER_VERSION);
+		output.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+		output.extend_from_slice(&self.fingerprint.to_le_bytes());
+		output.extend_from_slice(&(tokens.len() as u64).to_le_bytes());
+		output.extend_from_slice(&token_stream);
+		output.extend_from_slice(&crc32(&output).to_le_bytes());
+		writer.write_all(&output)
+	}
+
+	fn read_container(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+		// Parsed in place, so a mapped input is never copied
+		if input.len() < CONTAINER_HEADER_BYTES + 4 || &input[..4] != CONTAINER_MAGIC {
+			return Err("Not a BytePiece compressed file".to_string());
+		}
+		let (body, checksum) = input.split_at(input.len() - 4);
+		if crc32(body).to_le_bytes() != checksum {
+			return Err("Checksum mismatch; the file is corrupted".to_string());
+		}
+		if body[4] != CONTAINER_VERSION {
+			return Err(format!("Unsupported version {}; expected {}", body[4], CONTAINER_VERSION));
+		}
+		let read_u64 = |at: usize| u64::from_le_bytes(body[at..at + 8].try_into().unwrap());
+		let (original_length, fingerprint, token_count) = (read_u64(5), read_u64(13), read_u64(21));
+		if fingerprint != self.fingerprint {
+			return Err(format!("Encoded with another vocabulary; fingerprint {:016x}, expected {:016x}", fingerprint, self.fingerprint));
+		}
+
+		// Every token takes at least 1 bit, so a bad count can't allocate much
+		let token_stream = &body[CONTAINER_HEADER_BYTES..];
+		if token_count > token_stream.len() as u64 * 8 {
+			return Err(format!("{} tokens can't fit in {} bytes", token_count, token_stream.len()));
+		}
+		let tokens = self.huffman.decode(token_stream, token_count as usize)?;
+		let bytes = self.decode(&tokens)?;
+		if bytes.len() as u64 != original_length {
+			return Err(format!("Decoded {} bytes; expected {}", bytes.len(), original_length));
+		}
+		Ok(bytes)
+	}
+}
+
+
+pub fn count_token_usage(model: &BTreeMap<Vec<u8>, i32>, bytes: &[u8], file_bounds: &[std::ops::Range<usize>], mode: EncodeMode) -> BTreeMap<Vec<u8>, u64> {
+	// How many times each token is used to encode the bytes, so the trainer can see what a token is really worth
+	// Each file is encoded on its own, like the trainer sees them
+	let codec = TokCodec::from_model(model);
+	let mut usage = vec![0; codec.pieces.len()];
+	for file in file_bounds {
+		for id in codec.encode(&bytes[file.clone()], mode) {
+			usage[id as usize] += 1;
+		}
+	}
+	codec.pieces.into_iter().zip(usage).filter(|(piece, _)| !piece.is_empty()).collect()
+}
+
+pub fn encode_entry(param: &TokenizerParameters, model_path: &str, output_path: &str) -> Result<(), String> {
+	let mut codec = TokCodec::new(model_path)?;
+	if let Some(max_token_len) = param.max_token_len {
+		codec.limit_token_len(max_token_len);
+	}
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	if param.has_debug() { println!("Debug: Loaded {} tokens from {}", codec.pieces.len(), model_path); }
+
+	let mut output = vec![];
+	codec.write_container(bin_dat, param.encode_mode, &mut output).expect("Writing to memory can't fail");
+	std::fs::write(output_path, &output).map_err(|error| format!("Unable to write {}: {}", output_path, error))?;
+	if param.has_info() {
+		println!("Info: {:?}: {} bytes -> {} bytes ({:.2}%)", param.encode_mode, bin_dat.len(), output.len(), output.len() as f64 * 100.0 / bin_dat.len().max(1) as f64);
+	}
+	Ok(())
 }
 
+pub fn decode_entry(param: &TokenizerParameters, model_path: &str, output_path: &str) -> Result<(), String> {
+	let codec = TokCodec::new(model_path)?;
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	if param.has_debug() { println!("Debug: Loaded {} tokens from {}", codec.pieces.len(), model_path); }
+
+	let output = codec.read_container(bin_dat)?;
+	std::fs::write(output_path, &output).map_err(|error| format!("Unable to write {}: {}", output_path, error))?;
+	if param.has_info() { println!("Info: {} bytes -> {} bytes", bin_dat.len(), output.len()); }
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use crate::tok_codec::*;
+
+	fn test_codec(pieces: &[&[u8]]) -> TokCodec {
+		TokCodec::from_model(&pieces.iter().map(|piece| (piece.to_vec(), 1)).collect::<BTreeMap<_, _>>())
+	}
+
+	#[test]
+	fn test_encode() {
+		let codec = test_codec(&[b"ab", b"abc", b"bc"]);
+		// Same scores, so the learned IDs are in sorted order: ab = 259, abc = 260, bc = 261
+		assert_eq!(codec.encode(b"abcab", EncodeMode::Greedy), vec![260, 259]);
+		assert_eq!(codec.encode(b"bcabca", EncodeMode::Greedy), vec![261, 260, b'a' as u32]);
+		assert_eq!(codec.encode(b"", EncodeMode::Greedy), vec![]);
+		assert_eq!(codec.encode(b"abd\xff", EncodeMode::Greedy), vec![259, b'd' as u32, 0xff]);
+	}
+
+	#[test]
+	fn test_encode_viterbi() {
+		let codec = test_codec(&[b"ab", b"abc", b"cdef"]);
+		// abc = 260, ab = 259, cdef = 261
+		assert_eq!(codec.encode(b"abcdef", EncodeMode::Greedy), vec![260, b'd' as u32, b'e' as u32, b'f' as u32]);
+		assert_eq!(codec.encode(b"abcdef", EncodeMode::MinTokens), vec![259, 261]);
+		assert_eq!(codec.encode(b"", EncodeMode::MinTokens), vec![]);
 
-pub fn demo() {
-	let model = TokCodec::new("output.vocab.txt");
-	// println!("{:?}", model);
-	let tokens = model.encode(DEMO_STRING);
-	println!("{:?}", tokens);
+		let mut model = BTreeMap::new();
+		model.insert(b"aa".to_vec(), 1000);
+		model.insert(b"aaa".to_vec(), 0);
+		let codec = TokCodec::from_model(&model);
+		// aa = 259, aaa = 260
+		assert_eq!(codec.encode(b"aaaa", EncodeMode::MinBits), vec![259, 259]);
+		assert_eq!(codec.encode(b"aaaaa", EncodeMode::MinTokens).len(), 2);
+		for mode in [EncodeMode::Greedy, EncodeMode::MinTokens, EncodeMode::MinBits] {
+			let input = b"aaaaaaabcdefaaab";
+			assert_eq!(codec.decode(&codec.encode(input, mode)), Ok(input.to_vec()));
+		}
+	}
+
+	#[test]
+	fn test_encode_viterbi_windowed() {
+		let mut model = BTreeMap::new();
+		for (piece, score) in [(&b"ab"[..], 40), (b"abc", 30), (b"cdef", 20), (b"bcd", 10), (b"aa", 5), (b"aaa", 5)] {
+			model.insert(piece.to_vec(), score);
+		}
+		let codec = TokCodec::from_model(&model);
+		let input: Vec<u8> = b"abcdefabcdxaaaaaaaabcabcdef".iter().cycle().take(2000).copied().collect();
+		let min_tokens = |_| 1.0;
+		let min_bits = |id: u32| codec.token_bits[id as usize];
+		let whole = codec.encode_viterbi_windowed(&input, min_tokens, usize::MAX);
+		assert_eq!(codec.encode(&input, EncodeMode::MinTokens), whole);
+		assert_eq!(codec.encode_viterbi_windowed(&input, min_tokens, 16), whole);
+		let whole = codec.encode_viterbi_windowed(&input, min_bits, usize::MAX);
+		assert_eq!(codec.encode(&input, EncodeMode::MinBits), whole);
+		assert_eq!(codec.encode_viterbi_windowed(&input, min_bits, 16), whole);
+
+		// A cut where the paths don't agree costs a token at most
+		let run = vec![b'a'; 1000];
+		let tokens = codec.encode_viterbi_windowed(&run, |_| 1.0, 16);
+		assert_eq!(codec.decode(&tokens), Ok(run.clone()));
+		assert!(tokens.len() <= codec.encode_viterbi_windowed(&run, |_| 1.0, usize::MAX).len() + 1000 / 16);
+	}
+
+	#[test]
+	fn test_limit_token_len() {
+		let mut codec = test_codec(&[b"ab", b"abc", b"cdef"]);
+		let mut compressed = vec![];
+		codec.write_container(b"abcdefabc", EncodeMode::MinTokens, &mut compressed).unwrap();
+
+		codec.limit_token_len(3);
+		assert_eq!(codec.encode(b"abcdef", EncodeMode::MinTokens), vec![260, b'd' as u32, b'e' as u32, b'f' as u32]);
+		codec.limit_token_len(1);
+		assert_eq!(codec.encode(b"abc", EncodeMode::Greedy), vec![b'a' as u32, b'b' as u32, b'c' as u32]);
+		assert_eq!(codec.read_container(&compressed), Ok(b"abcdefabc".to_vec()));
+	}
+
+	#[test]
+	fn test_byte_trie() {
+		let mut trie = ByteTrie::new();
+		assert_eq!(trie.insert(b"abc", 1), None);
+		assert_eq!(trie.insert(b"a", 2), None);
+		assert_eq!(trie.insert(b"abcde", 3), None);
+		assert_eq!(trie.insert(b"b", 4), None);
+		assert_eq!(trie.insert(b"a", 5), Some(2));
+
+		assert_eq!(trie.prefixes(b"abcdef").collect::<Vec<_>>(), vec![(1, 5), (3, 1), (5, 3)]);
+		assert_eq!(trie.prefixes(b"abd").collect::<Vec<_>>(), vec![(1, 5)]);
+		assert_eq!(trie.prefixes(b"c").collect::<Vec<_>>(), vec![]);
+		assert_eq!(trie.prefixes(b"").collect::<Vec<_>>(), vec![]);
+	}
+
+	#[test]
+	fn test_decode() {
+		let codec = test_codec(&[b"ab", b"abc", b"bc"]);
+		assert_eq!(codec.decode(&[260, 259]), Ok(b"abcab".to_vec()));
+		assert_eq!(codec.decode(&[]), Ok(vec![]));
+		assert_eq!(codec.decode(&[256, 97, 258]), Ok(b"a".to_vec()));
+		assert!(codec.decode(&[0, 262]).is_err());
+
+		let input = b"cabcbcaabcab\x00\x80";
+		assert_eq!(codec.decode(&codec.encode(input, EncodeMode::Greedy)), Ok(input.to_vec()));
+	}
+
+	#[test]
+	fn test_vocab_file() {
+		let mut model = BTreeMap::new();
+		model.insert(b"ab".to_vec(), 3);
+		model.insert(b"abc".to_vec(), 7);
+		model.insert(b"b".to_vec(), 1);
+
+		let ids = assign_token_ids(&model);
+		assert_eq!(ids.len(), FIRST_LEARNED_TOKEN as usize + 2);
+		assert_eq!(ids[b'b' as usize], (b"b".to_vec(), 1));
+		assert_eq!(ids[FIRST_LEARNED_TOKEN as usize], (b"abc".to_vec(), 7));
+		assert_eq!(ids[FIRST_LEARNED_TOKEN as usize + 1], (b"ab".to_vec(), 3));
+
+		let file_path = std::env::temp_dir().join(format!("test_vocab_file_{}.vocab.txt", std::process::id()));
+		let file_path = file_path.to_str().unwrap();
+		write_vocab(file_path, &model);
+		let codec = TokCodec::new(file_path).unwrap();
+		std::fs::remove_file(file_path).unwrap();
+		assert_eq!(codec.pieces, ids.iter().map(|(bytes, _)| bytes.clone()).collect::<Vec<_>>());
+		assert_eq!(codec.fingerprint, TokCodec::from_model(&model).fingerprint);
+		assert_eq!(codec.encode(b"abcab", EncodeMode::MinTokens), vec![FIRST_LEARNED_TOKEN, FIRST_LEARNED_TOKEN + 1]);
+	}
+
+	#[test]
+	fn test_log_prob_vocab_file() {
+		let mut model: BTreeMap<Vec<u8>, f64> = (0..=u8::MAX).map(|byte| (vec![byte], -20.0)).collect();
+		model.insert(b"aa".to_vec(), -1.0);
+		model.insert(b"aaa".to_vec(), -6.5);
+		model.insert(b"a".to_vec(), -2.0);
+		model.insert(b"b".to_vec(), -3.0);
+
+		let file_path = std::env::temp_dir().join(format!("test_log_prob_vocab_file_{}.vocab.txt", std::process::id()));
+		let file_path = file_path.to_str().unwrap();
+		write_vocab(file_path, &model);
+		let written = std::fs::read_to_string(file_path).unwrap();
+		let codec = TokCodec::new(file_path).unwrap();
+		std::fs::remove_file(file_path).unwrap();
+		assert!(written.contains("259\t[97, 97]\t-1.0\n"));
+		assert!(written.contains("256\t<pad>\t0.0\n"));
+
+		// aa = 259, aaa = 260; "aaa" costs more than "aa" + "a"
+		assert_eq!(codec.encode(b"aaaa", EncodeMode::MinBits), vec![259, 259]);
+		assert!(!codec.encode(b"aaa", EncodeMode::MinBits).contains(&260));
+		let frequencies = log_prob_frequencies(&codec.pieces, &[0.0; 261]);
+		assert_eq!(frequencies[b'a' as usize], LOG_PROB_SCALE as u64 + 1);
+		assert_eq!(frequencies[256], 1);
+
+		let mut compressed = vec![];
+		codec.write_container(b"aaaaabab", EncodeMode::MinBits, &mut compressed).unwrap();
+		assert_eq!(codec.read_container(&compressed), Ok(b"aaaaabab".to_vec()));
+		assert_ne!(codec.fingerprint, TokCodec::from_model(&BTreeMap::from([(b"aa".to_vec(), -1), (b"aaa".to_vec(), -6)])).fingerprint);
+	}
+
+	#[test]
+	fn test_crc32() {
+		assert_eq!(crc32(b""), 0);
+		assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+	}
+
+	#[test]
+	fn test_container() {
+		let codec = test_codec(&[b"ab", b"abc", b"bc"]);
+		let input = b"abcabcbcbcaaaab\x00\xff".repeat(20);
+		let mut compressed = vec![];
+		codec.write_container(&input, EncodeMode::MinTokens, &mut compressed).unwrap();
+		assert_eq!(&compressed[..4], CONTAINER_MAGIC);
+		assert!(compressed.len() < input.len());
+		assert_eq!(codec.read_container(&compressed), Ok(input.clone()));
+
+		let mut empty = vec![];
+		codec.write_container(b"", EncodeMode::Greedy, &mut empty).unwrap();
+		assert_eq!(codec.read_container(&empty), Ok(vec![]));
+
+		let mut corrupted = compressed.clone();
+		corrupted[CONTAINER_HEADER_BYTES] ^= 1;
+		assert!(codec.read_container(&corrupted).unwrap_err().contains("Checksum"));
+		assert!(codec.read_container(&compressed[..20]).is_err());
+
+		let other_codec = test_codec(&[b"ab", b"abc"]);
+		assert!(other_codec.read_container(&compressed).unwrap_err().contains("vocabulary"));
+	}
 }
@@ -0,0 +1,128 @@
+// Count-Min Sketch: approximate counts in a fixed memory, never too low,
+// and too high by `error * total` at most with the `1 - e ** -SKETCH_DEPTH` probability
+// The heavy hitters are the only keys kept, so it's O(1 / error) memory instead of O(n ** 2 * 256)
+use std::collections::{BTreeMap, BTreeSet};
+
+
+// 4 rows, so an estimate is within the bound around 98% of the time
+const SKETCH_DEPTH: usize = 4;
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub fn fnv_step(hash: u64, byte: u8) -> u64 {
+	// FNV-1a one byte at a time, so the hashes of `data[i..=j]` come from the one of `data[i..j]`
+	(hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+}
+
+fn mix(hash: u64, row: usize) -> u64 {
+	// The SplitMix64 finalizer, one stream per row, so the rows collide on different keys
+	let mut z = hash.wrapping_add((row as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+	z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	z ^ (z >> 31)
+}
+
+pub struct CountMinSketch {
+	width: usize,
+	cells: Vec<u32>,
+}
+
+impl CountMinSketch {
+	pub fn new(error: f64, max_width: usize) -> Self {
+		// `e / error` wide is what makes the bound hold; no wider than the keys it'll ever see
+		let width = ((std::f64::consts::E / error).ceil() as usize).clamp(1, max_width.max(1));
+		CountMinSketch { width, cells: vec![0; width * SKETCH_DEPTH] }
+	}
+
+	pub fn increment(&mut self, hash: u64) -> u32 {
+		// Conservative update: only the cells below the new estimate go up to it,
+		// so the keys sharing a cell are overcounted less; returns the new estimate
+		let indices: [usize; SKETCH_DEPTH] = std::array::from_fn(|row| row * self.width + (mix(hash, row) % self.width as u64) as usize);
+		let estimate = indices.iter().map(|&index| self.cells[index]).min().unwrap().saturating_add(1);
+		for index in indices {
+			self.cells[index] = self.cells[index].max(estimate);
+		}
+		estimate
+	}
+}
+
+pub struct HeavyHitters {
+	// The keys with the `capacity` highest estimates so far
+	capacity: usize,
+	counts: BTreeMap<Vec<u8>, u32>,
+	// The key is in there so the keys with the same estimate are all held,
+	// and `pop_first()` evicts the lowest estimate, the smallest key of those
+	by_count: BTreeSet<(u32, Vec<u8>)>,
+}
+
+impl HeavyHitters {
+	pub fn new(capacity: usize) -> Self {
+		HeavyHitters { capacity: capacity.max(1), counts: BTreeMap::new(), by_count: BTreeSet::new() }
+	}
+
+	pub fn offer(&mut self, key: &[u8], estimate: u32) {
+		if let Some(count) = self.counts.get_mut(key) {
+			self.by_count.remove(&(*count, key.to_vec()));
+			*count = estimate;
+			self.by_count.insert((estimate, key.to_vec()));
+			return;
+		}
+		if self.counts.len() >= self.capacity {
+			match self.by_count.first() {
+				Some(&(lowest, _)) if lowest < estimate => {
+					let (_, lowest_key) = self.by_count.pop_first().unwrap();
+					self.counts.remove(&lowest_key);
+				}
+				_ => return,
+			}
+		}
+		self.counts.insert(key.to_vec(), estimate);
+		self.by_count.insert((estimate, key.to_vec()));
+	}
+
+	pub fn into_counts(self) -> BTreeMap<Vec<u8>, u32> {
+		self.counts
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use crate::tok_count_min::*;
+
+	fn hash(key: &[u8]) -> u64 {
+		key.iter().fold(FNV_OFFSET, |hash, &byte| fnv_step(hash, byte))
+	}
+
+	#[test]
+	fn test_count_min_sketch() {
+		let keys: Vec<Vec<u8>> = (0..200u32).map(|key| key.to_le_bytes().to_vec()).collect();
+		let mut sketch = CountMinSketch::new(0.05, usize::MAX);
+		let mut exact = BTreeMap::new();
+		let mut estimates = BTreeMap::new();
+		for (index, key) in keys.iter().enumerate() {
+			// Key `n` comes `n % 7 + 1` times
+			for _ in 0..index % 7 + 1 {
+				*exact.entry(key.clone()).or_insert(0) += 1;
+				estimates.insert(key.clone(), sketch.increment(hash(key)));
+			}
+		}
+		assert!(exact.iter().all(|(key, &count)| estimates[key] >= count));
+		let total: u32 = exact.values().sum();
+		let within = exact.iter().filter(|(key, &count)| (estimates[*key] - count) as f64 <= 0.05 * total as f64).count();
+		assert!(within * 10 >= exact.len() * 9);
+		assert_eq!(CountMinSketch::new(1e-9, 10).width, 10);
+	}
+
+	#[test]
+	fn test_heavy_hitters() {
+		let mut heavy_hitters = HeavyHitters::new(2);
+		heavy_hitters.offer(b"a", 2);
+		heavy_hitters.offer(b"b", 3);
+		heavy_hitters.offer(b"c", 2);
+		heavy_hitters.offer(b"a", 4);
+		heavy_hitters.offer(b"d", 5);
+		let counts = heavy_hitters.into_counts();
+		assert_eq!(counts.into_iter().collect::<Vec<_>>(), vec![(b"a".to_vec(), 4), (b"d".to_vec(), 5)]);
+	}
+}
@@ -0,0 +1,166 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+
+// Longer codes are flattened by halving the frequencies, so a code always fits in a `u32`
+const MAX_CODE_BITS: u8 = 24;
+
+#[derive(Debug)]
+pub struct HuffmanCode {
+	// Canonical Huffman code: only the lengths are needed to rebuild it,
+	// so both sides build the same code from the same frequencies
+	lengths: Vec<u8>,
+	codes: Vec<u32>,
+	// Decoding tables indexed by code length
+	first_code: Vec<u32>,
+	first_index: Vec<u32>,
+	length_count: Vec<u32>,
+	sorted_symbols: Vec<u32>,
+}
+
+fn code_lengths(frequencies: &[u64]) -> Vec<u8> {
+	// Builds the tree with a min heap of (frequency, node), the node breaks ties so it's deterministic
+	let mut parents = vec![usize::MAX; frequencies.len()];
+	let mut heap: BinaryHeap<Reverse<(u64, usize)>> = frequencies.iter().enumerate()
+		.map(|(symbol, &frequency)| Reverse((frequency, symbol)))
+		.collect();
+	while heap.len() > 1 {
+		let Reverse((left_frequency, left)) = heap.pop().unwrap();
+		let Reverse((right_frequency, right)) = heap.pop().unwrap();
+		let parent = parents.len();
+		parents.push(usize::MAX);
+		parents[left] = parent;
+		parents[right] = parent;
+		heap.push(Reverse((left_frequency + right_frequency, parent)));
+	}
+
+	// The parents are always created after their children, so walk down from the root
+	let mut depths = vec![0u32; parents.len()];
+	for node in (0..parents.len()).rev() {
+		if parents[node] != usize::MAX {
+			depths[node] = depths[parents[node]] + 1;
+		}
+	}
+	// A lone symbol still needs 1 bit
+	depths[..frequencies.len()].iter().map(|&depth| depth.clamp(1, u8::MAX as u32) as u8).collect()
+}
+
+impl HuffmanCode {
+	pub fn new(frequencies: &[u64]) -> HuffmanCode {
+		assert!(!frequencies.is_empty(), "Can't build a Huffman code without symbols");
+		let mut frequencies = frequencies.to_vec();
+		let lengths = loop {
+			let lengths = code_lengths(&frequencies);
+			if *lengths.iter().max().unwrap() <= MAX_CODE_BITS {
+				break lengths;
+			}
+			// Flatten the distribution until the deepest code fits
+			frequencies.iter_mut().for_each(|frequency| *frequency = *frequency / 2 + 1);
+		};
+
+		let mut sorted_symbols: Vec<u32> = (0..lengths.len() as u32).collect();
+		sorted_symbols.sort_by_key(|&symbol| (lengths[symbol as usize], symbol));
+
+		let mut codes = vec![0; lengths.len()];
+		let mut first_code = vec![0; MAX_CODE_BITS as usize + 1];
+		let mut first_index = vec![0; MAX_CODE_BITS as usize + 1];
+		let mut length_count = vec![0; MAX_CODE_BITS as usize + 1];
+		let mut code = 0u32;
+		let mut previous_length = 0;
+		for (index, &symbol) in sorted_symbols.iter().enumerate() {
+			let length = lengths[symbol as usize];
+			if length != previous_length {
+				code <<= length - previous_length;
+				first_code[length as usize] = code;
+				first_index[length as usize] = index as u32;
+				previous_length = length;
+			}
+			codes[symbol as usize] = code;
+			length_count[length as usize] += 1;
+			code += 1;
+		}
+		HuffmanCode { lengths, codes, first_code, first_index, length_count, sorted_symbols }
+	}
+
+	pub fn encode(&self, symbols: &[u32]) -> Vec<u8> {
+		// Most significant bit first, the last byte is padded with zeros
+		let mut output = vec![];
+		let mut buffer = 0u64;
+		let mut buffered_bits = 0;
+		for &symbol in symbols {
+			let length = self.lengths[symbol as usize];
+			buffer = (buffer << length) | self.codes[symbol as usize] as u64;
+			buffered_bits += length as u32;
+			while buffered_bits >= 8 {
+				buffered_bits -= 8;
+				output.push((buffer >> buffered_bits) as u8);
+			}
+		}
+		if buffered_bits > 0 {
+			output.push((buffer << (8 - buffered_bits)) as u8);
+		}
+		output
+	}
+
+	pub fn decode(&self, bytes: &[u8], symbol_count: usize) -> Result<Vec<u32>, String> {
+		let mut symbols = Vec::with_capacity(symbol_count);
+		let mut bits = bytes.iter().flat_map(|&byte| (0..8).rev().map(move |shift| (byte >> shift) as u32 & 1));
+		while symbols.len() < symbol_count {
+			let mut code = 0;
+			let mut length = 0;
+			loop {
+				code = (code << 1) | bits.next().ok_or("Truncated entropy coded stream")?;
+				length += 1;
+				if length > MAX_CODE_BITS as usize {
+					return Err("Invalid Huffman code".to_string());
+				}
+				let offset = code.wrapping_sub(self.first_code[length]);
+				if offset < self.length_count[length] {
+					symbols.push(self.sorted_symbols[(self.first_index[length] + offset) as usize]);
+					break;
+				}
+			}
+		}
+		Ok(symbols)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::tok_entropy::*;
+
+	#[test]
+	fn test_code_lengths() {
+		assert_eq!(code_lengths(&[1]), vec![1]);
+		assert_eq!(code_lengths(&[1, 1]), vec![1, 1]);
+		assert_eq!(code_lengths(&[8, 4, 2, 1, 1]), vec![1, 2, 3, 4, 4]);
+	}
+
+	#[test]
+	fn test_huffman_code() {
+		let huffman = HuffmanCode::new(&[8, 4, 2, 1, 1]);
+		// Canonical codes: 0, 10, 110, 1110, 1111
+		assert_eq!(huffman.codes, vec![0b0, 0b10, 0b110, 0b1110, 0b1111]);
+		assert_eq!(huffman.encode(&[0, 1, 2, 3, 4]), vec![0b0101_1011, 0b1011_1100]);
+
+		let symbols = vec![0, 0, 4, 1, 0, 3, 2, 2, 0, 1];
+		let encoded = huffman.encode(&symbols);
+		assert_eq!(huffman.decode(&encoded, symbols.len()), Ok(symbols.clone()));
+		assert!(huffman.decode(&encoded[..1], symbols.len()).is_err());
+		assert_eq!(huffman.decode(&[], 0), Ok(vec![]));
+	}
+
+	#[test]
+	fn test_huffman_length_limit() {
+		// Fibonacci frequencies make the deepest possible tree
+		let mut frequencies = vec![1u64, 1];
+		while frequencies.len() < 60 {
+			frequencies.push(frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2]);
+		}
+		let huffman = HuffmanCode::new(&frequencies);
+		assert!(huffman.lengths.iter().all(|&length| length <= MAX_CODE_BITS));
+
+		let symbols: Vec<u32> = (0..60).chain((0..60).rev()).collect();
+		assert_eq!(huffman.decode(&huffman.encode(&symbols), symbols.len()), Ok(symbols));
+	}
+}
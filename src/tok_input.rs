@@ -0,0 +1,73 @@
+// The input bytes of the trainer and the codec, read into memory or memory-mapped
+// A mapped file is paged in by the OS as it's used, so a multi-GB file doesn't need the RAM for a copy
+// The mapping is the "mmap" feature: it saves memory, not time, so STD only stays the default
+#[cfg(feature = "mmap")]
+use std::{fs::File, io};
+use std::ops::Deref;
+#[cfg(feature = "mmap")]
+use memmap2::{Mmap, MmapOptions};
+
+
+#[derive(Debug)]
+pub enum InputBytes {
+	Owned(Vec<u8>),
+	#[cfg(feature = "mmap")]
+	Mapped(Mmap),
+}
+
+#[cfg(feature = "mmap")]
+impl InputBytes {
+	pub fn map(file: &File, bytes_to_read: Option<u64>) -> io::Result<InputBytes> {
+		let len = min_len(file.metadata()?.len(), bytes_to_read);
+		if len == 0 {
+			// Mapping zero bytes fails on some systems
+			return Ok(InputBytes::Owned(vec![]));
+		}
+		// SAFETY: The mapping is read-only, but the file can still change under it if another process writes it;
+		// "mm=0" reads the file into memory instead
+		let mmap = unsafe { MmapOptions::new().len(len).map(file)? };
+		Ok(InputBytes::Mapped(mmap))
+	}
+}
+
+#[cfg(feature = "mmap")]
+fn min_len(file_len: u64, bytes_to_read: Option<u64>) -> usize {
+	usize::try_from(file_len.min(bytes_to_read.unwrap_or(u64::MAX))).unwrap_or(usize::MAX)
+}
+
+impl Deref for InputBytes {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match self {
+			InputBytes::Owned(data) => data,
+			#[cfg(feature = "mmap")]
+			InputBytes::Mapped(mmap) => mmap,
+		}
+	}
+}
+
+impl From<Vec<u8>> for InputBytes {
+	fn from(data: Vec<u8>) -> Self {
+		InputBytes::Owned(data)
+	}
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod tests {
+	use std::{fs::File, io::Read};
+	use crate::tok_input::*;
+
+	#[test]
+	fn test_map() {
+		let mut expected = vec![];
+		File::open("pexels-pixabay-302743.jpg").unwrap().read_to_end(&mut expected).unwrap();
+		let file = File::open("pexels-pixabay-302743.jpg").unwrap();
+		let mapped = InputBytes::map(&file, None).unwrap();
+		assert!(matches!(mapped, InputBytes::Mapped(_)));
+		assert_eq!(&*mapped, expected.as_slice());
+		assert_eq!(&*InputBytes::map(&file, Some(100)).unwrap(), &expected[..100]);
+		assert_eq!(InputBytes::map(&file, Some(0)).unwrap().len(), 0);
+		assert_eq!(&*InputBytes::from(b"abc".to_vec()), b"abc");
+	}
+}
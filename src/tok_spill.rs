@@ -0,0 +1,144 @@
+// Sorted runs of the summed models on disk and their k-way merge, for when the sums don't fit in memory
+// A run is a `BTreeMap` written in order: the key length as a u32, the key, then the score as an i32, little-endian
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+
+#[derive(Debug)]
+pub struct SpillRuns {
+	dir: PathBuf,
+	// The threads write their own runs, only the list of them is shared
+	runs: Mutex<Vec<PathBuf>>,
+}
+
+struct RunReader<R>(R);
+
+impl<R: Read> Iterator for RunReader<R> {
+	type Item = io::Result<(Vec<u8>, i32)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let mut len = [0; 4];
+		match self.0.read_exact(&mut len) {
+			Ok(()) => {}
+			Err(error) if error.kind() == ErrorKind::UnexpectedEof => return None,
+			Err(error) => return Some(Err(error)),
+		}
+		let mut key = vec![0; u32::from_le_bytes(len) as usize];
+		let mut score = [0; 4];
+		Some(self.0.read_exact(&mut key).and_then(|_| self.0.read_exact(&mut score)).map(|_| (key, i32::from_le_bytes(score))))
+	}
+}
+
+type Run = Box<dyn Iterator<Item = io::Result<(Vec<u8>, i32)>>>;
+
+impl SpillRuns {
+	pub fn new(dir: PathBuf) -> io::Result<SpillRuns> {
+		fs::create_dir_all(&dir)?;
+		Ok(SpillRuns { dir, runs: Mutex::new(vec![]) })
+	}
+
+	pub fn run_count(&self) -> usize {
+		self.runs.lock().unwrap().len()
+	}
+
+	pub fn spill(&self, model: &BTreeMap<Vec<u8>, i32>) -> io::Result<()> {
+		let path = {
+			let mut runs = self.runs.lock().unwrap();
+			let path = self.dir.join(format!("tokenizer_run_{}_{}.bin", std::process::id(), runs.len()));
+			runs.push(path.clone());
+			path
+		};
+		let mut file = BufWriter::new(File::create(&path)?);
+		for (key, score) in model {
+			file.write_all(&(key.len() as u32).to_le_bytes())?;
+			file.write_all(key)?;
+			file.write_all(&score.to_le_bytes())?;
+		}
+		file.flush()
+	}
+
+	pub fn merge(&self, model: BTreeMap<Vec<u8>, i32>, keep: impl Fn(&[u8], i32) -> bool) -> io::Result<BTreeMap<Vec<u8>, i32>> {
+		// k-way merge of the runs and the model still in memory, the same keys come out together so they're summed
+		// as they go; only the summed keys that `keep` are held in memory, then the runs are deleted
+		let paths = std::mem::take(&mut *self.runs.lock().unwrap());
+		let mut runs = vec![];
+		for path in &paths {
+			runs.push(Box::new(RunReader(BufReader::new(File::open(path)?))) as Run);
+		}
+		runs.push(Box::new(model.into_iter().map(Ok)));
+
+		// By the key first, so the same key from every run comes out together to be summed;
+		// the run only breaks the ties, and their order only matters once an i32 sum saturates
+		let mut heap = BinaryHeap::new();
+		for (index, run) in runs.iter_mut().enumerate() {
+			if let Some(entry) = run.next() {
+				let (key, score) = entry?;
+				heap.push(Reverse((key, index, score)));
+			}
+		}
+		let mut merged = BTreeMap::new();
+		let mut current: Option<(Vec<u8>, i32)> = None;
+		while let Some(Reverse((key, index, score))) = heap.pop() {
+			if let Some(entry) = runs[index].next() {
+				let (next_key, next_score) = entry?;
+				heap.push(Reverse((next_key, index, next_score)));
+			}
+			match &mut current {
+				Some((current_key, sum)) if *current_key == key => *sum = sum.saturating_add(score),
+				_ => if let Some((key, sum)) = current.replace((key, score)) {
+					if keep(&key, sum) {
+						merged.insert(key, sum);
+					}
+				}
+			}
+		}
+		if let Some((key, sum)) = current {
+			if keep(&key, sum) {
+				merged.insert(key, sum);
+			}
+		}
+		for path in paths {
+			fs::remove_file(path)?;
+		}
+		Ok(merged)
+	}
+}
+
+impl Drop for SpillRuns {
+	fn drop(&mut self) {
+		// The runs left by an error
+		for path in self.runs.lock().unwrap().iter() {
+			let _ = fs::remove_file(path);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use crate::tok_spill::*;
+
+	fn model(entries: &[(&[u8], i32)]) -> BTreeMap<Vec<u8>, i32> {
+		entries.iter().map(|&(key, score)| (key.to_vec(), score)).collect()
+	}
+
+	#[test]
+	fn test_spill_runs() {
+		let dir = std::env::temp_dir().join(format!("test_spill_runs_{}", std::process::id()));
+		let runs = SpillRuns::new(dir.clone()).unwrap();
+		runs.spill(&model(&[(b"ab", 3), (b"abc", 2), (b"z", -1)])).unwrap();
+		runs.spill(&model(&[])).unwrap();
+		runs.spill(&model(&[(b"ab", 4), (b"b", 1), (b"z", 1)])).unwrap();
+		assert_eq!(runs.run_count(), 3);
+
+		let merged = runs.merge(model(&[(b"abc", -2), (b"c", 5)]), |_, score| score > 0).unwrap();
+		assert_eq!(merged, model(&[(b"ab", 7), (b"b", 1), (b"c", 5)]));
+		assert_eq!(runs.run_count(), 0);
+		assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+		fs::remove_dir(dir).unwrap();
+	}
+}
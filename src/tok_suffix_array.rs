@@ -0,0 +1,111 @@
+// Suffix array helpers to count repeated substrings in near-linear memory
+// The text is `u32` so every segment can end with its own sentinel above 255,
+// then no common prefix can run across two segments
+
+
+pub fn suffix_array(text: &[u32]) -> Vec<usize> {
+	// Prefix doubling: O(n log^2 n) time, but only a few `Vec<usize>` of memory
+	let n = text.len();
+	let mut sa: Vec<usize> = (0..n).collect();
+	if n == 0 {
+		return sa;
+	}
+	let mut rank: Vec<usize> = text.iter().map(|&symbol| symbol as usize).collect();
+	let mut next_rank = vec![0; n];
+	let mut k = 1;
+	loop {
+		// A suffix that ends before `i + k` sorts first, hence the `+ 1`
+		let key = |i: usize| (rank[i], rank.get(i + k).map_or(0, |&r| r + 1));
+		sa.sort_unstable_by_key(|&i| key(i));
+		next_rank[sa[0]] = 0;
+		for index in 1..n {
+			next_rank[sa[index]] = next_rank[sa[index - 1]] + (key(sa[index - 1]) != key(sa[index])) as usize;
+		}
+		std::mem::swap(&mut rank, &mut next_rank);
+		if rank[sa[n - 1]] == n - 1 {
+			return sa;
+		}
+		k *= 2;
+	}
+}
+
+pub fn lcp_array(text: &[u32], sa: &[usize]) -> Vec<usize> {
+	// Kasai's algorithm; `lcp[i]` is the longest common prefix of the suffixes `sa[i - 1]` and `sa[i]`
+	let n = text.len();
+	let mut rank = vec![0; n];
+	for (index, &suffix) in sa.iter().enumerate() {
+		rank[suffix] = index;
+	}
+	let mut lcp = vec![0; n];
+	let mut common = 0;
+	for suffix in 0..n {
+		if rank[suffix] == 0 {
+			common = 0;
+			continue;
+		}
+		let previous = sa[rank[suffix] - 1];
+		while suffix + common < n && previous + common < n && text[suffix + common] == text[previous + common] {
+			common += 1;
+		}
+		lcp[rank[suffix]] = common;
+		common = common.saturating_sub(1);
+	}
+	lcp
+}
+
+pub fn for_each_repeat(sa: &[usize], lcp: &[usize], mut visit: impl FnMut(usize, std::ops::Range<usize>, usize)) {
+	// Walks the LCP intervals bottom-up, they're the internal nodes of the suffix tree
+	// `visit(count, lengths, suffix)`: the prefixes of `suffix` with the lengths occur `count` times,
+	// every repeated substring is visited exactly once
+	let mut stack: Vec<(usize, usize)> = vec![(0, 0)]; // (lcp, left bound)
+	for index in 1..=sa.len() {
+		let current = lcp.get(index).copied().unwrap_or(0);
+		let mut left = index - 1;
+		while current < stack.last().unwrap().0 {
+			let (interval_lcp, left_bound) = stack.pop().unwrap();
+			left = left_bound;
+			let parent_lcp = current.max(stack.last().unwrap().0);
+			visit(index - left_bound, parent_lcp + 1..interval_lcp + 1, sa[left_bound]);
+		}
+		if current > stack.last().unwrap().0 {
+			stack.push((current, left));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use crate::tok_suffix_array::*;
+
+	fn text_of(bytes: &[u8]) -> Vec<u32> {
+		bytes.iter().map(|&byte| byte as u32).collect()
+	}
+
+	#[test]
+	fn test_suffix_array() {
+		let text = text_of(b"banana");
+		let sa = suffix_array(&text);
+		assert_eq!(sa, vec![5, 3, 1, 0, 4, 2]);
+		assert_eq!(lcp_array(&text, &sa), vec![0, 1, 3, 0, 0, 2]);
+		assert_eq!(suffix_array(&[]), vec![]);
+		assert_eq!(suffix_array(&[7, 7, 7]), vec![2, 1, 0]);
+	}
+
+	#[test]
+	fn test_for_each_repeat() {
+		let text = text_of(b"banana");
+		let sa = suffix_array(&text);
+		let mut repeats = BTreeMap::new();
+		for_each_repeat(&sa, &lcp_array(&text, &sa), |count, lengths, suffix| {
+			for len in lengths {
+				assert_eq!(repeats.insert(b"banana"[suffix..suffix + len].to_vec(), count), None);
+			}
+		});
+		let expected: BTreeMap<Vec<u8>, usize> = [
+			(b"a".to_vec(), 3), (b"an".to_vec(), 2), (b"ana".to_vec(), 2),
+			(b"n".to_vec(), 2), (b"na".to_vec(), 2),
+		].into_iter().collect();
+		assert_eq!(repeats, expected);
+	}
+}
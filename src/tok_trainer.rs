@@ -1,83 +1,352 @@
+use std::borrow::Cow;
 use std::cmp::min;
+use std::io::{ErrorKind, Read};
 // Use STD only, avoid external dependencies unless it speeds up by 3x!!!!!
-use std::fs::File;
-use std::io::Write;
-use std::ops::AddAssign;
 use std::panic;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 // Don't use those garbage collection stuffs unless you really need it!
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}};
 use rayon::prelude::*;
-use crate::TokenizerParameters;
+use crate::{TokenizerParameters, tok_codec};
+use crate::tok_suffix_array::{suffix_array, lcp_array, for_each_repeat};
+use crate::tok_byte_pair::{train_byte_pair, merges_to_model, merges_path, write_merges};
+use crate::tok_unigram::{self, train_unigram};
+use crate::tok_cdc::content_defined_chunks;
+use crate::tok_count_min::{CountMinSketch, HeavyHitters, FNV_OFFSET, fnv_step};
 
 
+pub const DEFAULT_OUTPUT_PATH: &str = "output.vocab.txt";
+// Bytes read from a source at a time by the streaming trainer
+const STREAM_BLOCK_BYTES: usize = 1 << 20;
+// Rough bytes of a count besides its key bytes: the `Vec` header, the count, and its share of the tree node
+const MAP_ENTRY_BYTES: usize = std::mem::size_of::<Vec<u8>>() + 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CountBackend {
+	// Every substring of a chunk goes into a `BTreeMap`, around O(n ** 2 * 256) memory
+	#[default]
+	BTreeMap,
+	// Suffix and LCP arrays of the chunk, O(n) memory besides the repeated substrings
+	SuffixArray,
+	// Count-Min Sketch and its heavy hitters, O(1 / error) memory; the counts can be too high by `error * n ** 2` or so
+	CountMinSketch { error: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChunkingMode {
+	// Every `trainer_chk_bytes`, `bin_dat.chunks()` style
+	#[default]
+	Fixed,
+	// Cut by the content with `trainer_chk_bytes` on average, so the same bytes get the same chunks
+	ContentDefined,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TrainerMode {
+	// Best substring of each chunk, then cut the chunk by it, see `greedy_bpe_encode()`
+	#[default]
+	Greedy,
+	// Classic BPE over the whole data, also writes the ordered merges
+	BytePair,
+	// SentencePiece's unigram language model, writes log-probabilities instead of the scores
+	Unigram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PruningPolicy {
+	// Every substring is counted until the chunk is done, around O(n ** 2 * 256) memory
+	KeepAll,
+	// Every `interval` substrings, the keys counted `min_count` times or less go
+	MinCount { interval: usize, min_count: usize },
+	// Only the `keys` highest counts stay, pruned whenever there are twice as many
+	TopK { keys: usize },
+	// Space-Saving heavy hitters: at most `keys` counts, a new key replaces the lowest one
+	SpaceSaving { keys: usize },
+}
+
+impl Default for PruningPolicy {
+	fn default() -> Self {
+		// What the counting always did before it could be chosen
+		PruningPolicy::MinCount { interval: 0xf_ffff, min_count: 1 }
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct PruningMetrics {
+	// Shared by the threads, so they're atomic; "passes" are how many times keys were dropped
+	policy_passes: AtomicU64,
+	policy_dropped: AtomicU64,
+	budget_passes: AtomicU64,
+	budget_dropped: AtomicU64,
+}
+
+impl PruningMetrics {
+	fn add_policy(&self, dropped: usize) {
+		self.policy_passes.fetch_add(1, Ordering::Relaxed);
+		self.policy_dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+	}
+
+	fn add_budget(&self, dropped: usize) {
+		self.budget_passes.fetch_add(1, Ordering::Relaxed);
+		self.budget_dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+	}
+}
+
 #[derive(Debug, PartialEq)]
-struct ConcatenatedBytes {
+struct ConcatenatedBytes<'a> {
 	// A structure that replaces `Vec<Vec<u8>>` by storing data in a concatenated `Vec<u8>`
 	// This will avoid the overhead of multiple `Vec<u8>` allocations and providing memory-contiguous storage
 	// But profiled with VTune, this didn't seem to speed up in noticeable way
-	data: Vec<u8>,
+	// Borrowed from the input until the chunks are rebuilt, so a mapped input is never copied
+	data: Cow<'a, [u8]>,
 	bounds: Vec<std::ops::Range<usize>>,
 }
 
-impl ConcatenatedBytes {
-	fn new(data: Vec<u8>, bounds: Vec<std::ops::Range<usize>>) -> Self {
-		ConcatenatedBytes { data, bounds }
+impl<'a> ConcatenatedBytes<'a> {
+	fn new(data: impl Into<Cow<'a, [u8]>>, bounds: Vec<std::ops::Range<usize>>) -> Self {
+		ConcatenatedBytes { data: data.into(), bounds }
 	}
 
 	// Don't even bother with methods because they're obfuscated
 }
 
-// It'll let `sum_byte_pair_encoding()` accept i16 and i32 inputs
-trait SumBPE: Copy + AddAssign<Self> + Into<i32> {}
-impl SumBPE for i16 {}
-impl SumBPE for i32 {}
+// It'll let `sum_byte_pair_encoding()` and the counting accept i16 to u64 counters
+// The arithmetic saturates, so a long or repetitive chunk can't overflow the smaller ones
+trait SumBPE: Copy + Ord + std::fmt::Debug {
+	const ONE: Self;
+	fn saturating_add(self, rhs: Self) -> Self;
+	fn saturating_sub(self, rhs: Self) -> Self;
+	fn saturating_mul(self, rhs: Self) -> Self;
+	fn from_usize(value: usize) -> Self;
+	fn to_i32(self) -> i32;
+}
+
+macro_rules! impl_sum_bpe {
+	($($t:ty),*) => {
+		$(
+			impl SumBPE for $t {
+				const ONE: Self = 1;
+				fn saturating_add(self, rhs: Self) -> Self { <$t>::saturating_add(self, rhs) }
+				fn saturating_sub(self, rhs: Self) -> Self { <$t>::saturating_sub(self, rhs) }
+				fn saturating_mul(self, rhs: Self) -> Self { <$t>::saturating_mul(self, rhs) }
+				fn from_usize(value: usize) -> Self { <$t>::try_from(value).unwrap_or(<$t>::MAX) }
+				fn to_i32(self) -> i32 { i32::try_from(self).unwrap_or(i32::MAX) }
+			}
+		)*
+	}
+}
+
+impl_sum_bpe!(i16, i32, u32, u64);
 
 
-fn train_unigram_bytes(byte_vec: &ConcatenatedBytes, dropout: Option<u32>, pre_keyed_map: Option<&BTreeMap<Vec<u8>, i16>>) -> BTreeMap<Vec<u8>, i16> {
-	fn drop_keys(mut loop_count: u32, dropout: u32, mut counter: BTreeMap<Vec<u8>, i16>) -> (u32, BTreeMap<Vec<u8>, i16>) {
-		loop_count += 1;
-		if loop_count % dropout == 0 {
-			// Dropping keys that are less than 2 saves 85% on average memory
-			// But it dropped keys that might have been more than 1 count late because they were far away
-			counter.retain(|_, &mut count| count > 1);
+fn entry_bytes(key_len: usize) -> usize {
+	key_len + MAP_ENTRY_BYTES
+}
+
+fn prune_to_bytes<C: Ord + Copy>(counter: &mut BTreeMap<Vec<u8>, C>, max_bytes: usize) -> usize {
+	// Drops the lowest counts until the map takes half of `max_bytes`, so it won't prune again right away
+	// Whole counts go at once, so a single `retain` on the threshold gets under the budget
+	// without choosing between the keys of the same count
+	// Returns the bytes the map takes after
+	let mut bytes_by_count = BTreeMap::new();
+	for (key, &count) in counter.iter() {
+		*bytes_by_count.entry(count).or_insert(0) += entry_bytes(key.len());
+	}
+	let mut bytes: usize = bytes_by_count.values().sum();
+	if bytes <= max_bytes {
+		return bytes;
+	}
+	let mut threshold = None;
+	for (&count, &count_bytes) in &bytes_by_count {
+		if bytes <= max_bytes / 2 {
+			break;
 		}
-		(loop_count, counter)
+		bytes -= count_bytes;
+		threshold = Some(count);
 	}
+	if let Some(threshold) = threshold {
+		counter.retain(|_, &mut count| count > threshold);
+	}
+	bytes
+}
+
+fn keep_top_k<C: Ord + Copy>(counter: &mut BTreeMap<Vec<u8>, C>, k: usize) {
+	// The sort is stable over the keys in order, so of the keys tied at the `k`-th count the smaller ones stay
+	let mut entries: Vec<(Vec<u8>, C)> = std::mem::take(counter).into_iter().collect();
+	entries.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+	entries.truncate(k);
+	*counter = entries.into_iter().collect();
+}
+
+fn train_unigram_bytes<C: SumBPE>(byte_vec: &ConcatenatedBytes, policy: PruningPolicy, pre_keyed_map: Option<&BTreeMap<Vec<u8>, C>>, max_token_len: Option<usize>, max_bytes: Option<usize>, metrics: &PruningMetrics) -> BTreeMap<Vec<u8>, C> {
 	// This function takes up 50% of CPU time on average of the whole program
 	// Pre-keying will result in much fewer memcmp
 	let mut counter = pre_keyed_map.unwrap_or(&BTreeMap::new()).clone(); // BTreeMap is faster than HashMap; profiled with VTune
-	let dropout = dropout.unwrap_or(0xf_ffff);
+	// Space-Saving takes the lowest count from here, ordered by the count then the key
+	let space_saving = matches!(policy, PruningPolicy::SpaceSaving { .. });
+	let index_counts = |counter: &BTreeMap<Vec<u8>, C>| -> BTreeSet<(C, Vec<u8>)> {
+		if space_saving { counter.iter().map(|(key, &count)| (count, key.clone())).collect() } else { BTreeSet::new() }
+	};
+	let mut by_count = index_counts(&counter);
+	// Bounding the length makes it O(n * max_token_len) keys rather than O(n ** 2)
+	let max_token_len = max_token_len.unwrap_or(usize::MAX);
+	// "max_mem=": the bytes are tracked as the keys come, and the lowest counts go when it's over
+	let max_bytes = max_bytes.unwrap_or(usize::MAX);
+	let key_bytes = |counter: &BTreeMap<Vec<u8>, C>| -> usize { counter.keys().map(|key| entry_bytes(key.len())).sum() };
+	let mut bytes = key_bytes(&counter);
 
-	let mut loop_count = 0;
+	let mut loop_count: usize = 0;
 	for bound in &byte_vec.bounds {
 		for i in bound.start..bound.end {
-			for j in i..bound.end {
+			for j in i..min(bound.end, i.saturating_add(max_token_len)) {
 				// Memory write and read reduction idea: Compress slices with a JPG tokenizer
-				let slice = byte_vec.data[i..j + 1].to_vec();
+				let slice = &byte_vec.data[i..j + 1];
 				// Unlock memory bandwidth ​with AVX2: Replace Vec<u8> with __m256i
-				*counter.entry(slice).or_insert(0) += 1;
-				(loop_count, counter) = drop_keys(loop_count, dropout, counter);
+				let count = match counter.get_mut(slice) {
+					Some(count) => {
+						if space_saving {
+							by_count.remove(&(*count, slice.to_vec()));
+						}
+						*count = count.saturating_add(C::ONE);
+						*count
+					}
+					None => {
+						let mut count = C::ONE;
+						if let PruningPolicy::SpaceSaving { keys } = policy {
+							if counter.len() >= keys.max(1) {
+								// The new key takes the place and the count of the lowest one,
+								// so a count is too high by the count it took at most, never too low
+								let (lowest, key) = by_count.pop_first().unwrap();
+								counter.remove(&key);
+								bytes -= entry_bytes(key.len());
+								metrics.add_policy(1);
+								count = lowest.saturating_add(C::ONE);
+							}
+						}
+						counter.insert(slice.to_vec(), count);
+						bytes += entry_bytes(slice.len());
+						count
+					}
+				};
+				if space_saving {
+					by_count.insert((count, slice.to_vec()));
+				}
+
+				if bytes > max_bytes {
+					let keys = counter.len();
+					bytes = prune_to_bytes(&mut counter, max_bytes);
+					metrics.add_budget(keys - counter.len());
+					by_count = index_counts(&counter);
+				}
+				loop_count += 1;
+				let keys = counter.len();
+				match policy {
+					PruningPolicy::MinCount { interval, min_count } if loop_count.is_multiple_of(interval.max(1)) => {
+						// Dropping keys that are less than 2 saves 85% on average memory
+						// But it dropped keys that might have been more than 1 count late because they were far away
+						counter.retain(|_, &mut count| count > C::from_usize(min_count));
+					}
+					PruningPolicy::TopK { keys } if counter.len() >= keys.max(1).saturating_mul(2) => keep_top_k(&mut counter, keys),
+					_ => continue,
+				}
+				metrics.add_policy(keys - counter.len());
+				bytes = key_bytes(&counter);
 			}
 		}
 	}
-	counter.retain(|_, &mut count| count > 1);
+	counter.retain(|_, &mut count| count > C::ONE);
+	counter
+}
+
+fn suffix_array_text(byte_vec: &ConcatenatedBytes) -> Vec<u32> {
+	// Each bound ends with its own sentinel, so no substring is counted across two bounds
+	let mut text = Vec::with_capacity(byte_vec.data.len() + byte_vec.bounds.len());
+	for (index, bound) in byte_vec.bounds.iter().enumerate() {
+		text.extend(byte_vec.data[bound.clone()].iter().map(|&byte| byte as u32));
+		text.push(u8::MAX as u32 + 1 + index as u32);
+	}
+	text
+}
+
+fn train_unigram_bytes_suffix_array<C: SumBPE>(byte_vec: &ConcatenatedBytes, max_token_len: Option<usize>) -> BTreeMap<Vec<u8>, C> {
+	// The same counts as `train_unigram_bytes()` without the dropout,
+	// but only the repeated substrings are ever kept in memory
+	let text = suffix_array_text(byte_vec);
+	let sa = suffix_array(&text);
+	let mut counter = BTreeMap::new();
+	let max_token_len = max_token_len.unwrap_or(usize::MAX);
+	for_each_repeat(&sa, &lcp_array(&text, &sa), |count, lengths, suffix| {
+		for len in lengths.start..min(lengths.end, max_token_len.saturating_add(1)) {
+			let slice = text[suffix..suffix + len].iter().map(|&symbol| symbol as u8).collect();
+			counter.insert(slice, C::from_usize(count));
+		}
+	});
 	counter
 }
 
-fn numerical_grade_encodable(byte_vec: &ConcatenatedBytes, counter: &BTreeMap<Vec<u8>, i16>) -> BTreeMap<Vec<u8>, i16> {
+fn train_unigram_bytes_count_min<C: SumBPE>(byte_vec: &ConcatenatedBytes, max_token_len: Option<usize>, error: f64) -> BTreeMap<Vec<u8>, C> {
+	// Like `train_unigram_bytes()`, but the counts are estimated by the sketch and only the `1 / error`
+	// highest ones are kept as keys; an estimate is never too low, so a frequent key is hardly ever missed
+	let max_token_len = max_token_len.unwrap_or(usize::MAX);
+	let substrings = byte_vec.bounds.iter().map(|bound| (0..bound.len()).map(|i| min(bound.len() - i, max_token_len)).sum::<usize>()).sum();
+	let mut sketch = CountMinSketch::new(error, substrings);
+	let mut heavy_hitters = HeavyHitters::new((1.0 / error).ceil() as usize);
+	for bound in &byte_vec.bounds {
+		for i in bound.start..bound.end {
+			let mut hash = FNV_OFFSET;
+			for j in i..min(bound.end, i.saturating_add(max_token_len)) {
+				hash = fnv_step(hash, byte_vec.data[j]);
+				let estimate = sketch.increment(hash);
+				// The ones seen once go at the end anyway
+				if estimate > 1 {
+					heavy_hitters.offer(&byte_vec.data[i..j + 1], estimate);
+				}
+			}
+		}
+	}
+	heavy_hitters.into_counts().into_iter().map(|(k, v)| (k, C::from_usize(v as usize))).collect()
+}
+
+fn best_subvector_suffix_array(byte_vec: &ConcatenatedBytes, max_token_len: Option<usize>) -> Option<(Vec<u8>, u64)> {
+	// The same as `numerical_grade_encodable()` then `max_by_key()` without keeping any substring
+	// The score `count * (len - 1)` grows with the length, so only the longest of each interval can be the best,
+	// and ties go to the greater bytes just like `max_by_key()` over a `BTreeMap`
+	let text = suffix_array_text(byte_vec);
+	let sa = suffix_array(&text);
+	let max_token_len = max_token_len.unwrap_or(usize::MAX);
+	let mut best: Option<(u64, std::ops::Range<usize>)> = None;
+	for_each_repeat(&sa, &lcp_array(&text, &sa), |count, lengths, suffix| {
+		if lengths.start > max_token_len {
+			return;
+		}
+		let len = min(lengths.end - 1, max_token_len);
+		let score = count as u64 * (len as u64 - 1);
+		let range = suffix..suffix + len;
+		let is_better = match &best {
+			None => true,
+			Some((best_score, best_range)) => (score, &text[range.clone()]) > (*best_score, &text[best_range.clone()]),
+		};
+		if is_better {
+			best = Some((score, range));
+		}
+	});
+	best.map(|(score, range)| (text[range].iter().map(|&symbol| symbol as u8).collect(), score))
+}
+
+fn numerical_grade_encodable<C: SumBPE>(byte_vec: &ConcatenatedBytes, counter: &BTreeMap<Vec<u8>, C>) -> BTreeMap<Vec<u8>, C> {
 	// It returns the `count * sub.len() - count * byte_size` as the score,
 	// and assumes that storing tokens take only one byte
 	// But, most likely this is not the case but two bytes
-	let byte_size = 1;
+	// The counts are exact here, and a candidate seen once is left out: the approximate counters,
+	// the Count-Min Sketch and SpaceSaving, can put one over `count > 1`, and it'd still score `sub.len() - 1`
+	let byte_size = C::ONE;
 	let mut scores = BTreeMap::new();
 	for sub in counter.keys() {
-		scores.insert(sub.to_vec(), 0);
-	}
-	for range in &byte_vec.bounds {
-		for sub in counter.keys() {
-			let count = byte_vec.data[range.clone()].windows(sub.len()).filter(|w| w == sub).count() as i16;
-			*scores.get_mut(sub).unwrap() += count * sub.len() as i16 - count * byte_size;
+		let count: usize = byte_vec.bounds.iter()
+			.map(|range| byte_vec.data[range.clone()].windows(sub.len()).filter(|w| w == sub).count())
+			.sum();
+		if count >= 2 {
+			scores.insert(sub.to_vec(), C::from_usize(count).saturating_mul(C::from_usize(sub.len()).saturating_sub(byte_size)));
 		}
 	}
 	scores
@@ -114,7 +383,7 @@ fn generate_cutoff_by_pattern(byte_vec: &ConcatenatedBytes, pattern: &[u8]) -> V
 		let mut inverted_ranges = vec![];
 
 		if merged_ranges[0].start != byte_index {
-			inverted_ranges = vec![byte_index..merged_ranges[0].start];
+			inverted_ranges.push(byte_index..merged_ranges[0].start);
 		}
 
 		for range in merged_ranges {
@@ -130,7 +399,7 @@ fn generate_cutoff_by_pattern(byte_vec: &ConcatenatedBytes, pattern: &[u8]) -> V
 	for byte_range in &byte_vec.bounds {
 		let inner_vec = &byte_vec.data[byte_range.clone()];
 
-		let subbyte_output = all_subbyte(&inner_vec, pattern, None);
+		let subbyte_output = all_subbyte(inner_vec, pattern, None);
 		if subbyte_output.is_empty() {
 			continue;
 		}
@@ -153,63 +422,189 @@ fn generate_cutoff_by_pattern(byte_vec: &ConcatenatedBytes, pattern: &[u8]) -> V
 	cutoff
 }
 
-fn rebuild_2d_byte_vec(cutoff: &[std::ops::Range<usize>], byte: &ConcatenatedBytes) -> ConcatenatedBytes {
-	let mut new_byte = ConcatenatedBytes::new(vec![], vec![]);
+fn rebuild_2d_byte_vec(cutoff: &[std::ops::Range<usize>], byte: &ConcatenatedBytes) -> ConcatenatedBytes<'static> {
+	let mut data = vec![];
+	let mut bounds = vec![];
 	for range in cutoff {
 		if range.start != range.end {
 			let slice = &byte.data[range.clone()];
-			new_byte.data.extend_from_slice(slice);
-			new_byte.bounds.push(new_byte.data.len() - slice.len()..new_byte.data.len());
+			data.extend_from_slice(slice);
+			bounds.push(data.len() - slice.len()..data.len());
 		} else {
 			panic!("Maybe generate_cutoff_by_pattern have bugs.\nbyte: {:?}\ncutoff: {:?}", byte, cutoff);
 		}
 	}
-	new_byte
+	ConcatenatedBytes::new(data, bounds)
 }
 
-fn greedy_bpe_encode(byte: &[u8]) -> BTreeMap<Vec<u8>, i16> {
+fn greedy_bpe_encode(byte: &[u8], param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
+	// The highest score is `count * sub.len()` at most, which is around `byte.len() ** 2 / 4`
+	// or `byte.len() * max_token_len`, so the narrowest counter that can't overflow is used to save memory
+	let max_token_len = param.max_token_len.unwrap_or(usize::MAX) as u128;
+	let max_score = min((byte.len() as u128).pow(2) / 4, byte.len() as u128 * max_token_len);
+	if max_score <= i16::MAX as u128 {
+		greedy_bpe_encode_counted::<i16>(byte, param)
+	} else if max_score <= u32::MAX as u128 {
+		greedy_bpe_encode_counted::<u32>(byte, param)
+	} else {
+		greedy_bpe_encode_counted::<u64>(byte, param)
+	}
+}
+
+fn greedy_bpe_encode_counted<C: SumBPE>(byte: &[u8], param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
 	// This method will encode the byte pair encoding using `count * sub.len() - count * byte_size`
 	// greedy scoring method without testing every single combinations so it'd be fast
 	// But, who knows if this will result in optimal size
-	let mut byte_vec = ConcatenatedBytes::new(byte.to_vec(), vec![0..byte.len()]);
+	#[allow(clippy::single_range_in_vec_init)] // One bound covering the whole chunk
+	let mut byte_vec = ConcatenatedBytes::new(byte, vec![0..byte.len()]);
 	let mut tokenizer_model = BTreeMap::new();
 
 	while !byte_vec.data.is_empty() {
-		let counter = train_unigram_bytes(&byte_vec, None, None);
-		if counter.is_empty() {
+		let best = match param.count_backend {
+			CountBackend::BTreeMap => {
+				let counter = train_unigram_bytes::<C>(&byte_vec, param.pruning_policy, None, param.max_token_len, memory_share(param), &param.pruning_metrics);
+				let scores = numerical_grade_encodable(&byte_vec, &counter);
+				scores.into_iter().max_by_key(|(_, v)| *v).map(|(k, v)| (k, v.to_i32()))
+			}
+			CountBackend::CountMinSketch { error } => {
+				// The candidates are scored with their exact counts, so only the choice of them is approximate
+				let counter = train_unigram_bytes_count_min::<C>(&byte_vec, param.max_token_len, error);
+				let scores = numerical_grade_encodable(&byte_vec, &counter);
+				scores.into_iter().max_by_key(|(_, v)| *v).map(|(k, v)| (k, v.to_i32()))
+			}
+			CountBackend::SuffixArray => best_subvector_suffix_array(&byte_vec, param.max_token_len)
+				.map(|(k, v)| (k, i32::try_from(v).unwrap_or(i32::MAX))),
+		};
+		let Some((best_subvector, best_score)) = best else {
 			return tokenizer_model;
-		}
-
-		let scores = numerical_grade_encodable(&byte_vec, &counter);
+		};
 
-		let (best_subvector, best_score) = scores.iter().max_by_key(|(_, v)| *v).unwrap();
-
-		if 2 >= *best_score {
+		if 2 >= best_score {
 			return tokenizer_model;
 		}
 
-		tokenizer_model.insert(best_subvector.clone(), *best_score);
-
-		let cutoff = generate_cutoff_by_pattern(&byte_vec, best_subvector);
+		let cutoff = generate_cutoff_by_pattern(&byte_vec, &best_subvector);
+		tokenizer_model.insert(best_subvector, best_score);
 
 		// Memory read and write optimization idea: Don't resize the vector and just rebuild the bounds only
-		// Maybe it's possible to convert `ConcatenatedBytes` from `Vec<u8>` to `&[u8]` to speed this up
 		byte_vec = rebuild_2d_byte_vec(&cutoff, &byte_vec);
 	}
 	tokenizer_model
 }
 
-fn sum_byte_pair_encoding<S: SumBPE>(tokenizer: &BTreeMap<Vec<u8>, i32>, stats: &BTreeMap<Vec<u8>, S>) -> BTreeMap<Vec<u8>, i32> {
+fn sum_byte_pair_encoding<S: SumBPE>(tokenizer: BTreeMap<Vec<u8>, i32>, stats: &BTreeMap<Vec<u8>, S>, bytes: &mut usize) -> BTreeMap<Vec<u8>, i32> {
 	// Increasing trainable range idea: Create a bf16 imitation to store higher values in the BPE
 	// But this will increase the update resistance as the number goes up due to quantized rounding errors,
 	// and slowing down the program by not using the built-in ASM instructions but software emulating the bf16 type
-	stats.into_iter().fold(tokenizer.clone(), |mut summed_model, (key, value)| {
-		*summed_model.entry(key.to_vec()).or_insert(0) += (*value).into();
+	// Taken by value, so the summed model isn't copied for every chunk
+	// `bytes` is what the model takes, kept up as keys are added so "max_mem=" doesn't go over the whole model
+	stats.iter().fold(tokenizer, |mut summed_model, (key, value)| {
+		let summed = summed_model.entry(key.to_vec()).or_insert_with(|| {
+			*bytes += entry_bytes(key.len());
+			0
+		});
+		*summed = summed.saturating_add(value.to_i32());
 		summed_model
 	})
 }
 
-fn train_tokenizer_rayon_multi_threaded(param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
+fn chunk_cuts(file: std::ops::Range<usize>, bin_dat: &[u8], param: &TokenizerParameters, overlap: usize) -> Vec<std::ops::Range<usize>> {
+	// The cuts of one file, a file always starts a new chunk
+	// The fixed cuts leave room for the overlap, so a chunk is still `trainer_chk_bytes` with it
+	let chunk_length = param.trainer_chk_bytes.unwrap_or(16).max(1);
+	let cuts = match param.chunking_mode {
+		ChunkingMode::Fixed => (0..file.len()).step_by(chunk_length - overlap)
+			.map(|start| start..min(start + chunk_length - overlap, file.len()))
+			.collect(),
+		ChunkingMode::ContentDefined => content_defined_chunks(&bin_dat[file.clone()], chunk_length),
+	};
+	cuts.into_iter().map(|cut| cut.start + file.start..cut.end + file.start).collect()
+}
+
+fn chunk_overlap(param: &TokenizerParameters) -> usize {
+	min(param.trainer_chk_overlap.unwrap_or(0), param.trainer_chk_bytes.unwrap_or(16).max(1) - 1)
+}
+
+fn window_chunks<'a>(bin_dat: &'a [u8], file: std::ops::Range<usize>, cuts: &[std::ops::Range<usize>], overlap: usize, end_of_file: bool) -> (Vec<(&'a [u8], bool)>, usize) {
+	// Each cut is extended by `trainer_chk_overlap` bytes into the next one,
+	// so a pattern across a boundary is still seen whole by a chunk
+	// The bytes shared by two neighbors are trained on their own too and subtracted, `true` marks those;
+	// it only takes a token back if the shared bytes alone pick the same one the chunks did,
+	// so a token in the shared bytes can be counted twice, and one picked only alone goes below zero
+	// and is dropped by `subtract_lengths()`
+	// The neighbors are enough because the chunks start and end in order,
+	// what a chunk shares with the one after the next is shared with the next too
+	// Also returns where the unused cuts start: before the end of the file,
+	// a chunk that reaches the end of the bytes might still grow, so it waits for more
+	let mut chunks = vec![];
+	for (index, cut) in cuts.iter().enumerate() {
+		let end = min(cut.end + overlap, file.end);
+		if end == file.end && !end_of_file {
+			return (chunks, cut.start);
+		}
+		chunks.push((&bin_dat[cut.start..end], false));
+		if end == file.end {
+			break;
+		}
+		if overlap > 0 {
+			chunks.push((&bin_dat[cuts[index + 1].start..end], true));
+		}
+	}
+	(chunks, file.end)
+}
+
+fn split_chunks(param: &TokenizerParameters) -> Vec<(&[u8], bool)> {
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	let overlap = chunk_overlap(param);
+	param.file_bounds().into_iter().flat_map(|file| {
+		let cuts = chunk_cuts(file.clone(), bin_dat, param, overlap);
+		window_chunks(bin_dat, file, &cuts, overlap, true).0
+	}).collect()
+}
+
+fn greedy_bpe_encode_signed(chunk: &[u8], subtract: bool, param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
+	let mut bpe = greedy_bpe_encode(chunk, param);
+	if subtract {
+		bpe.values_mut().for_each(|v| *v = v.saturating_neg());
+	}
+	bpe
+}
+
+fn memory_share(param: &TokenizerParameters) -> Option<usize> {
+	// "max_mem=" split evenly: each thread has the counts of a chunk, their scores, and its summed model,
+	// and there's the model summed from all the threads
+	let threads = match param.multi_threaded {
+		None => 1,
+		Some(0) => num_cpus::get_physical(),
+		Some(threads) => threads,
+	};
+	param.max_mem.map(|max_mem| max_mem / (3 * threads + 1))
+}
+
+fn prune_model(param: &TokenizerParameters, mut tokenizer_model: BTreeMap<Vec<u8>, i32>, bytes: &mut usize) -> BTreeMap<Vec<u8>, i32> {
+	// Over its share of "max_mem=", the model goes to disk as a sorted run with "spill=", else its lowest scores go
+	// `bytes` is the running total from `sum_byte_pair_encoding()`, so the model is only gone over once it's over
+	if let Some(max_bytes) = memory_share(param) {
+		if *bytes <= max_bytes {
+			return tokenizer_model;
+		}
+		if let Some(spill_runs) = &param.spill_runs {
+			spill_runs.spill(&tokenizer_model).expect("Unable to write the spill run");
+			if param.has_debug() { println!("Debug: max_mem: Spilled {} tokens to run {}", tokenizer_model.len(), spill_runs.run_count()); }
+			*bytes = 0;
+			return BTreeMap::new();
+		}
+		let tokens = tokenizer_model.len();
+		*bytes = prune_to_bytes(&mut tokenizer_model, max_bytes);
+		if tokenizer_model.len() < tokens {
+			param.pruning_metrics.add_budget(tokens - tokenizer_model.len());
+		}
+		if param.has_debug() && tokenizer_model.len() < tokens { println!("Debug: max_mem: Pruned the model from {} to {} tokens", tokens, tokenizer_model.len()); }
+	}
+	tokenizer_model
+}
+
+fn thread_pool(param: &TokenizerParameters) -> rayon::ThreadPool {
 	// Don't use hyper-threading as it uses twice as much memory for a 5% improvement only
 	let num_threads = if param.multi_threaded.unwrap() == 0 {
 		let cores = num_cpus::get_physical();
@@ -219,86 +614,283 @@ fn train_tokenizer_rayon_multi_threaded(param: &TokenizerParameters) -> BTreeMap
 		param.multi_threaded.unwrap()
 	};
 	if param.has_debug() { println!("Debug: Will use {} thread(s)", num_threads); }
-	let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap();
+	rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap()
+}
 
-	let chunk_length = param.trainer_chk_bytes.unwrap();
-	let bin_vec = param.bin_dat.clone().unwrap();
-	let chunks: Vec<&[u8]> = bin_vec.chunks(chunk_length).collect();
-	let group_size = (chunks.len() + num_threads - 1) / num_threads;
-	let groups: Vec<&[&[u8]]> = chunks.chunks(group_size).collect();
-	if param.has_debug() { println!("Debug: Chunk length: {} * {}", chunk_length, chunks.len()); }
+fn train_chunks_multi_threaded(param: &TokenizerParameters, pool: &rayon::ThreadPool, chunks: &[(&[u8], bool)]) -> BTreeMap<Vec<u8>, i32> {
+	let group_size = chunks.len().div_ceil(pool.current_num_threads()).max(1);
+	let groups: Vec<&[(&[u8], bool)]> = chunks.chunks(group_size).collect();
+	if param.has_debug() { println!("Debug: Chunk length: {} * {}", param.trainer_chk_bytes.unwrap_or(16), chunks.len()); }
 
 	// Completed; Multi-cores idea: Sum the model within threads,
 	// the memory usage should be limited to the number of threads rather than a vector,
 	// and, it doesn't have to use mutexes to merge the model except at the end
-	let tokenizer_model = Arc::new(Mutex::new(BTreeMap::new()));
+	// The summed model and the bytes it takes
+	let tokenizer_model = Arc::new(Mutex::new((BTreeMap::new(), 0)));
 	pool.install(|| groups.par_iter().for_each(|&group| {
-		let local_model = group.into_iter().fold(BTreeMap::new(), |mut local_model, &line| {
-			let bpe = greedy_bpe_encode(line);
-			local_model = sum_byte_pair_encoding(&local_model, &bpe);
-			local_model
+		let mut local_bytes = 0;
+		let local_model = group.iter().fold(BTreeMap::new(), |local_model, &(line, subtract)| {
+			let bpe = greedy_bpe_encode_signed(line, subtract, param);
+			prune_model(param, sum_byte_pair_encoding(local_model, &bpe, &mut local_bytes), &mut local_bytes)
 		});
 
 		let mut tokenizer_model_guard = tokenizer_model.lock().unwrap();
-		*tokenizer_model_guard = sum_byte_pair_encoding(&*tokenizer_model_guard, &local_model);
+		let (summed_model, bytes) = &mut *tokenizer_model_guard;
+		*summed_model = prune_model(param, sum_byte_pair_encoding(std::mem::take(summed_model), &local_model, bytes), bytes);
 	}));
 
-	Arc::try_unwrap(tokenizer_model).unwrap().into_inner().unwrap()
+	Arc::try_unwrap(tokenizer_model).unwrap().into_inner().unwrap().0
+}
+
+fn train_chunks_single_thread(param: &TokenizerParameters, chunks: &[(&[u8], bool)]) -> BTreeMap<Vec<u8>, i32> {
+	let mut tokenizer_model = BTreeMap::new();
+	let mut bytes = 0;
+	if param.has_debug() { println!("Debug: Will use single thread only on {} chunk(s)", chunks.len()); }
+	for &(chunk, subtract) in chunks {
+		let bpe = greedy_bpe_encode_signed(chunk, subtract, param);
+		tokenizer_model = prune_model(param, sum_byte_pair_encoding(tokenizer_model, &bpe, &mut bytes), &mut bytes);
+	}
+	tokenizer_model
+}
+
+fn train_tokenizer_rayon_multi_threaded(param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
+	train_chunks_multi_threaded(param, &thread_pool(param), &split_chunks(param))
 }
 
 fn train_tokenizer_single_thread(param: &TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
+	train_chunks_single_thread(param, &split_chunks(param))
+}
+
+fn train_tokenizer_streaming(param: &TokenizerParameters, sources: impl Iterator<Item = Box<dyn Read>>, block_bytes: usize, byte_counts: &mut [u64; 256]) -> Result<BTreeMap<Vec<u8>, i32>, String> {
+	// The same chunks as `split_chunks()`, but only the bytes of the unfinished chunks are kept,
+	// so the input can be any size; each source is a file of its own
+	// Also counts the bytes, since they're gone by the end
+	let pool = param.multi_threaded.map(|_| thread_pool(param));
+	let overlap = chunk_overlap(param);
+	let mut remaining = param.bytes_to_read.unwrap_or(u64::MAX);
 	let mut tokenizer_model = BTreeMap::new();
-	let bin_dat = param.bin_dat.clone().unwrap();
-	let chunks = bin_dat.chunks(param.trainer_chk_bytes.unwrap());
+	let mut bytes = 0;
+	let mut block = vec![0; block_bytes.max(1)];
 
-	if param.has_debug() { println!("Debug: Will use single thread only on {} chunk(s)", chunks.len()); }
-	for chunk in chunks {
-		let line = chunk.to_vec();
-		tokenizer_model = sum_byte_pair_encoding(&tokenizer_model, &greedy_bpe_encode(&line));
+	for source in sources {
+		let mut source = source.take(remaining);
+		let mut pending = vec![];
+		loop {
+			let read = match source.read(&mut block) {
+				Ok(read) => read,
+				Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+				Err(error) => return Err(format!("Unable to read the input: {}", error)),
+			};
+			remaining -= read as u64;
+			block[..read].iter().for_each(|&byte| byte_counts[byte as usize] += 1);
+			pending.extend_from_slice(&block[..read]);
+
+			let end_of_file = read == 0;
+			let file = 0..pending.len();
+			let cuts = chunk_cuts(file.clone(), &pending, param, overlap);
+			let (chunks, used) = window_chunks(&pending, file, &cuts, overlap, end_of_file);
+			let model = match &pool {
+				Some(pool) => train_chunks_multi_threaded(param, pool, &chunks),
+				None => train_chunks_single_thread(param, &chunks),
+			};
+			tokenizer_model = prune_model(param, sum_byte_pair_encoding(tokenizer_model, &model, &mut bytes), &mut bytes);
+			pending.drain(..used);
+			if end_of_file {
+				break;
+			}
+		}
 	}
+	Ok(tokenizer_model)
+}
+
+fn subtract_lengths(tokenizer_model: BTreeMap<Vec<u8>, i32>) -> BTreeMap<Vec<u8>, i32> {
+	// Only the subtracted overlaps can leave a token at zero or below
 	tokenizer_model
+		.into_iter()
+		.filter(|(_, v)| *v > 0)
+		.map(|(k, v)| (k.clone(), v - k.len() as i32))
+		.collect()
+}
+
+fn merge_spilled(param: &TokenizerParameters, tokenizer_model: BTreeMap<Vec<u8>, i32>) -> BTreeMap<Vec<u8>, i32> {
+	// The runs summed with the rest in memory; only the positive sums are kept, `subtract_lengths()` drops the others anyway
+	match &param.spill_runs {
+		Some(spill_runs) if spill_runs.run_count() > 0 => {
+			if param.has_info() { println!("Info: Merging {} spilled run(s)", spill_runs.run_count()); }
+			spill_runs.merge(tokenizer_model, |_, score| score > 0).expect("Unable to merge the spill runs")
+		}
+		_ => tokenizer_model,
+	}
 }
 
 pub fn train_tokenizer(param: &mut TokenizerParameters) -> BTreeMap<Vec<u8>, i32> {
 	param.trainer_chk_bytes = match param.trainer_chk_bytes {
 		Some(chunk_length) => Some(chunk_length),
 		None => {
-			let default_chunk_length = min(16, param.bin_dat.clone().unwrap().len());
+			let default_chunk_length = min(16, param.bin_dat.as_deref().unwrap().len());
 			if param.has_info() { println!("Info: chunk_length is None; assigning chunk(s) with length {}", default_chunk_length); }
 			Some(default_chunk_length)
 		}
 	};
-	let tokenizer_model;
-	if param.multi_threaded != None {
-		tokenizer_model = train_tokenizer_rayon_multi_threaded(param)
+	let tokenizer_model = if param.multi_threaded.is_some() {
+		train_tokenizer_rayon_multi_threaded(param)
 	} else {
-		tokenizer_model = train_tokenizer_single_thread(param)
+		train_tokenizer_single_thread(param)
+	};
+	let tokenizer_model = subtract_lengths(merge_spilled(param, tokenizer_model));
+	match param.vocab_size {
+		Some(vocab_size) => prune_vocab(param, tokenizer_model, vocab_size),
+		None => tokenizer_model,
+	}
+}
+
+fn unigram_seeds(param: &TokenizerParameters) -> BTreeMap<Vec<u8>, u64> {
+	// The repeated substrings of each chunk, the most frequent by `count * sub.len()` like SentencePiece
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	let bounds = param.file_bounds().into_iter().flat_map(|file| chunk_cuts(file, bin_dat, param, 0)).collect();
+	let byte_vec = ConcatenatedBytes::new(bin_dat, bounds);
+	let max_token_len = Some(param.max_token_len.unwrap_or(tok_unigram::DEFAULT_MAX_PIECE_LEN));
+	let counter = match param.count_backend {
+		// The input isn't copied, so the counts get all of "max_mem="
+		CountBackend::BTreeMap => train_unigram_bytes::<u32>(&byte_vec, param.pruning_policy, None, max_token_len, param.max_mem, &param.pruning_metrics),
+		CountBackend::SuffixArray => train_unigram_bytes_suffix_array::<u32>(&byte_vec, max_token_len),
+		CountBackend::CountMinSketch { error } => train_unigram_bytes_count_min::<u32>(&byte_vec, max_token_len, error),
+	};
+
+	let mut seeds: Vec<(Vec<u8>, u64)> = counter.into_iter()
+		.filter(|(k, _)| k.len() > 1)
+		.map(|(k, v)| (k, v as u64))
+		.collect();
+	seeds.sort_by_key(|(k, v)| std::cmp::Reverse(v * k.len() as u64));
+	seeds.truncate(tok_unigram::SEED_SIZE);
+	if param.has_debug() { println!("Debug: {} unigram seed pieces", seeds.len()); }
+	seeds.into_iter().collect()
+}
+
+fn rescore_vocab(param: &TokenizerParameters, tokenizer_model: &BTreeMap<Vec<u8>, i32>) -> BTreeMap<Vec<u8>, i32> {
+	// The same `count * sub.len() - count * byte_size - sub.len()` score as the training,
+	// but the count is how many times the codec really uses the token on the whole data
+	let usage = tok_codec::count_token_usage(tokenizer_model, param.bin_dat.as_deref().unwrap(), &param.file_bounds(), param.encode_mode);
+	tokenizer_model.keys().map(|k| {
+		let len = k.len() as i64;
+		let score = usage[k] as i64 * (len - 1) - len;
+		(k.clone(), score.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+	}).collect()
+}
+
+fn prune_vocab(param: &TokenizerParameters, mut tokenizer_model: BTreeMap<Vec<u8>, i32>, vocab_size: usize) -> BTreeMap<Vec<u8>, i32> {
+	// `vocab_size` counts the fallback bytes and the special tokens too, just like SentencePiece
+	// Overlapping tokens split the bytes they cover in the re-scoring, so they don't count twice,
+	// and a quarter is dropped at most in each round so the others get re-scored in between
+	let learned_size = vocab_size.saturating_sub(tok_codec::FIRST_LEARNED_TOKEN as usize);
+	tokenizer_model.retain(|k, _| k.len() > 1);
+	while tokenizer_model.len() > learned_size {
+		let scores = rescore_vocab(param, &tokenizer_model);
+		let drop_count = min(tokenizer_model.len() - learned_size, (tokenizer_model.len() / 4).max(1));
+		let mut ranked: Vec<(&Vec<u8>, &i32)> = scores.iter().collect();
+		ranked.sort_by_key(|(_, &score)| score);
+		for (k, _) in ranked.into_iter().take(drop_count) {
+			tokenizer_model.remove(k);
+		}
+		if param.has_debug() { println!("Debug: Pruned {} token(s); {} left", drop_count, tokenizer_model.len()); }
+	}
+	rescore_vocab(param, &tokenizer_model)
+}
+
+fn prune_vocab_by_score(mut tokenizer_model: BTreeMap<Vec<u8>, i32>, vocab_size: usize) -> BTreeMap<Vec<u8>, i32> {
+	// For the streaming trainer, the data is gone so the tokens can't be re-scored like `prune_vocab()`
+	let learned_size = vocab_size.saturating_sub(tok_codec::FIRST_LEARNED_TOKEN as usize);
+	tokenizer_model.retain(|k, _| k.len() > 1);
+	let mut ranked: Vec<(Vec<u8>, i32)> = tokenizer_model.into_iter().collect();
+	ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+	ranked.truncate(learned_size);
+	ranked.into_iter().collect()
+}
+
+fn count_fallback_bytes(byte_counts: &[u64; 256], tokenizer_model: &mut BTreeMap<Vec<u8>, i32>) {
+	// Single bytes never save anything, so their score is the count instead;
+	// the codec needs it to know how often it'll fall back to the bytes
+	for (byte, &count) in byte_counts.iter().enumerate() {
+		tokenizer_model.insert(vec![byte as u8], i32::try_from(count).unwrap_or(i32::MAX));
+	}
+}
+
+fn byte_counts(bin_dat: &[u8]) -> [u64; 256] {
+	let mut byte_counts = [0u64; 256];
+	bin_dat.iter().for_each(|&byte| byte_counts[byte as usize] += 1);
+	byte_counts
+}
+
+fn report_pruning(param: &TokenizerParameters) {
+	let metrics = &param.pruning_metrics;
+	let (policy_passes, budget_passes) = (metrics.policy_passes.load(Ordering::Relaxed), metrics.budget_passes.load(Ordering::Relaxed));
+	if param.has_info() && policy_passes > 0 {
+		println!("Info: Pruning: {:?} dropped {} keys in {} passes", param.pruning_policy, metrics.policy_dropped.load(Ordering::Relaxed), policy_passes);
+	}
+	if param.has_info() && budget_passes > 0 {
+		println!("Info: Pruning: max_mem dropped {} keys in {} passes", metrics.budget_dropped.load(Ordering::Relaxed), budget_passes);
 	}
-	tokenizer_model
-		.into_iter()
-		.map(|(k, v)| (k.clone(), v - k.len() as i32))
-		.collect()
 }
 
 pub fn entry(tok_trainer_args: &mut TokenizerParameters) {
-	let bin_dat = tok_trainer_args.bin_dat.as_ref().unwrap();
-	if tok_trainer_args.has_info() { println!("Info: File byte size: {}", bin_dat.len()); }
-	let result = train_tokenizer(tok_trainer_args);
-	if tok_trainer_args.has_lengthy() { println!("Lengthy: greedy_bpe_encode: {:?}, length: {}", result, result.len()); }
+	let bin_dat = tok_trainer_args.bin_dat.as_deref().unwrap();
+	if tok_trainer_args.has_info() { println!("Info: File byte size: {} in {} file(s)", bin_dat.len(), tok_trainer_args.file_bounds().len()); }
+	let output_path = tok_trainer_args.output_path.clone().unwrap_or(DEFAULT_OUTPUT_PATH.to_string());
+	if tok_trainer_args.trainer_mode == TrainerMode::Unigram {
+		// The log-probabilities cover the fallback bytes too, so there's nothing to count after
+		let seeds = unigram_seeds(tok_trainer_args);
+		report_pruning(tok_trainer_args);
+		let result = train_unigram(tok_trainer_args, &seeds);
+		if tok_trainer_args.has_info() { println!("Info: Writing {} tokens to {}", result.len(), output_path); }
+		tok_codec::write_vocab(&output_path, &result);
+		return;
+	}
+	let mut result = match tok_trainer_args.trainer_mode {
+		TrainerMode::Greedy => train_tokenizer(tok_trainer_args),
+		TrainerMode::BytePair => {
+			let merges = train_byte_pair(tok_trainer_args);
+			let merges_path = merges_path(&output_path);
+			if tok_trainer_args.has_info() { println!("Info: Writing {} merges to {}", merges.len(), merges_path); }
+			write_merges(&merges_path, &merges);
+			merges_to_model(&merges)
+		}
+		TrainerMode::Unigram => unreachable!("The unigram model is written above"),
+	};
+	report_pruning(tok_trainer_args);
+	if tok_trainer_args.has_lengthy() { println!("Lengthy: {:?}: {:?}, length: {}", tok_trainer_args.trainer_mode, result, result.len()); }
 
-	let mut file = File::create("output.vocab.txt").expect("create failed");
-	for (byte_vec, score) in result {
-		writeln!(file, "{:?}\t{}", byte_vec, score).expect("write failed");
+	count_fallback_bytes(&byte_counts(tok_trainer_args.bin_dat.as_deref().unwrap()), &mut result);
+	if tok_trainer_args.has_info() { println!("Info: Writing {} tokens to {}", result.len(), output_path); }
+	tok_codec::write_vocab(&output_path, &result);
+}
+
+pub fn stream_entry(tok_trainer_args: &TokenizerParameters, sources: impl Iterator<Item = Box<dyn Read>>) -> Result<(), String> {
+	// Greedy training only; the other modes need all the data at once
+	let mut byte_counts = [0; 256];
+	let tokenizer_model = train_tokenizer_streaming(tok_trainer_args, sources, STREAM_BLOCK_BYTES, &mut byte_counts)?;
+	if tok_trainer_args.has_info() { println!("Info: Streamed byte size: {}", byte_counts.iter().sum::<u64>()); }
+	report_pruning(tok_trainer_args);
+	let mut result = subtract_lengths(merge_spilled(tok_trainer_args, tokenizer_model));
+	if let Some(vocab_size) = tok_trainer_args.vocab_size {
+		result = prune_vocab_by_score(result, vocab_size);
 	}
+	if tok_trainer_args.has_lengthy() { println!("Lengthy: Streaming: {:?}, length: {}", result, result.len()); }
+
+	count_fallback_bytes(&byte_counts, &mut result);
+	let output_path = tok_trainer_args.output_path.as_deref().unwrap_or(DEFAULT_OUTPUT_PATH);
+	if tok_trainer_args.has_info() { println!("Info: Writing {} tokens to {}", result.len(), output_path); }
+	tok_codec::write_vocab(output_path, &result);
+	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-	use std::{collections::BTreeMap, io::{BufReader, Read}};
+	use std::{collections::BTreeMap, fs::File, io::{BufReader, Read}};
 	use crate::{tok_trainer::*, debug_enum};
 
 	#[test]
 	fn test_train_unigram_bytes() {
+		let metrics = PruningMetrics::default();
 		let byte_vec = ConcatenatedBytes::new(vec![
 				1, 2, 3, 1, 2, 3, 1, 2,
 				3, 1, 2, 3, 1, 2, 3, 1,
@@ -307,12 +899,157 @@ mod tests {
 		);
 
 		let test_dropout = 0x3fff;
-		let counter = train_unigram_bytes(&byte_vec, Some(test_dropout), None);
+		let counter = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::MinCount { interval: test_dropout, min_count: 1 }, None, None, None, &metrics);
 
 		assert_eq!(*counter.get(&vec![1, 2]).unwrap(), 5);
 		assert_eq!(*counter.get(&vec![3, 1]).unwrap(), 5);
 		assert_eq!(*counter.get(&vec![1, 2, 3]).unwrap(), 4);
 		assert_eq!(*counter.get(&vec![3, 1, 2]).unwrap(), 4);
+
+		let counter = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::default(), None, Some(2), None, &metrics);
+		assert!(counter.keys().all(|key| key.len() <= 2));
+		assert_eq!(*counter.get(&vec![1, 2]).unwrap(), 5);
+	}
+
+	#[test]
+	fn test_pruning_policy() {
+		let metrics = PruningMetrics::default();
+		let byte_vec = ConcatenatedBytes::new([b"abababababxyzab".as_slice(), b"ababqrstuvab"].concat(), vec![0..15, 15..27]);
+		let exact = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::KeepAll, None, None, None, &metrics);
+		assert_eq!(exact, train_unigram_bytes_suffix_array::<i16>(&byte_vec, None));
+		assert_eq!(metrics.policy_passes.load(Ordering::Relaxed), 0);
+
+		// Every new key goes before it's seen again
+		let counter = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::MinCount { interval: 1, min_count: 1 }, None, None, None, &metrics);
+		assert!(counter.is_empty());
+		assert!(metrics.policy_dropped.load(Ordering::Relaxed) > 0);
+
+		let metrics = PruningMetrics::default();
+		let counter = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::TopK { keys: 4 }, None, None, None, &metrics);
+		assert!(counter.len() < 8 && counter.iter().all(|(key, count)| count <= &exact[key]));
+		assert!(counter.contains_key(b"ab".as_slice()));
+		assert!(metrics.policy_passes.load(Ordering::Relaxed) > 0);
+
+		// The heavy hitters stay and the counts are never too low
+		let counter = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::SpaceSaving { keys: 8 }, None, None, None, &metrics);
+		assert!(counter.len() <= 8 && counter.iter().all(|(key, count)| count >= exact.get(key).unwrap_or(&0)));
+		assert!([b"a".as_slice(), b"b", b"ab"].iter().all(|key| counter.contains_key(*key)));
+
+		// A new key takes the count of the lowest one, so it's over 1 without repeating, but can't make a token
+		let space_saving_param = TokenizerParameters { pruning_policy: PruningPolicy::SpaceSaving { keys: 8 }, ..Default::default() };
+		let chunk: Vec<u8> = (0..=255).collect();
+		#[allow(clippy::single_range_in_vec_init)] // One bound covering the whole chunk
+		let byte_vec = ConcatenatedBytes::new(chunk.as_slice(), vec![0..chunk.len()]);
+		assert!(train_unigram_bytes::<u32>(&byte_vec, space_saving_param.pruning_policy, None, None, None, &metrics).values().any(|&count| count > 1));
+		assert_eq!(greedy_bpe_encode(&chunk, &space_saving_param), BTreeMap::new());
+	}
+
+	#[test]
+	fn test_count_min_backend() {
+		let metrics = PruningMetrics::default();
+		let byte_vec = ConcatenatedBytes::new([b"abcabcabcxyzxyzab".as_slice(), b"the cat sat on the mat"].concat(), vec![0..17, 17..39]);
+		let exact = train_unigram_bytes::<u32>(&byte_vec, PruningPolicy::KeepAll, None, None, None, &metrics);
+		// Never too low, and the most frequent keys are all there
+		let approximate = train_unigram_bytes_count_min::<u32>(&byte_vec, None, 0.01);
+		assert!(approximate.len() <= 100);
+		assert!(exact.iter().all(|(key, count)| approximate.get(key).is_none_or(|estimate| estimate >= count)));
+		assert!(exact.iter().filter(|(_, &count)| count >= 3).all(|(key, _)| approximate.contains_key(key)));
+		// A big error keeps a few keys only
+		assert!(train_unigram_bytes_count_min::<u32>(&byte_vec, None, 0.2).len() <= 5);
+
+		let chunk = b"abcabcabcabcxyzxyzabcabcabc";
+		let count_min_param = TokenizerParameters { count_backend: CountBackend::CountMinSketch { error: 0.001 }, ..Default::default() };
+		assert_eq!(greedy_bpe_encode(chunk, &count_min_param), greedy_bpe_encode(chunk, &TokenizerParameters::default()));
+		// Nothing repeats, so the overestimated counts mustn't make tokens
+		let chunk: Vec<u8> = (0..=255).collect();
+		let count_min_param = TokenizerParameters { count_backend: CountBackend::CountMinSketch { error: 0.2 }, ..Default::default() };
+		assert_eq!(greedy_bpe_encode(&chunk, &count_min_param), BTreeMap::new());
+	}
+
+	#[test]
+	fn test_memory_budget() {
+		let metrics = PruningMetrics::default();
+		let mut counter: BTreeMap<Vec<u8>, i16> = [(b"a".to_vec(), 1), (b"b".to_vec(), 1), (b"c".to_vec(), 2), (b"d".to_vec(), 5)].into_iter().collect();
+		assert_eq!(prune_to_bytes(&mut counter, entry_bytes(1) * 4), entry_bytes(1) * 4);
+		assert_eq!(counter.len(), 4);
+		// Down to half of the budget, the lowest counts first
+		assert_eq!(prune_to_bytes(&mut counter, entry_bytes(1) * 3), entry_bytes(1));
+		assert_eq!(counter.keys().collect::<Vec<_>>(), vec![b"d"]);
+
+		let byte_vec = ConcatenatedBytes::new(b"abcabcabcabcxyzxyzabcabcabc".to_vec(), vec![0..12, 12..27]);
+		let exact = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::default(), None, None, None, &metrics);
+		let pruned = train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::default(), None, None, Some(entry_bytes(4) * 64), &metrics);
+		assert!(pruned.len() < exact.len());
+		assert!(pruned.iter().all(|(key, count)| count <= &exact[key]));
+		assert!(pruned.contains_key(b"abc".as_slice()));
+
+		let mut param = TokenizerParameters {
+			bin_dat: Some(b"abcabcabcabcxyzxyzabcabcabc".repeat(4).into()), trainer_chk_bytes: Some(54), ..Default::default()
+		};
+		let exact = train_tokenizer(&mut param);
+		param.max_mem = Some((3 + 1) * entry_bytes(8) * 32);
+		let pruned = train_tokenizer(&mut param);
+		// Fewer counts, but still the repeats
+		assert!(exact.keys().all(|key| key.starts_with(b"abcabc")));
+		assert!(!pruned.is_empty() && pruned.keys().all(|key| key.starts_with(b"abcabc")));
+	}
+
+	#[test]
+	fn test_spill_runs() {
+		// Every 8 bytes is a pair of letters 4 times, so the sums grow with each chunk
+		let bin_dat: Vec<u8> = (0..400).flat_map(|i| [b'a' + (i % 26) as u8, b'a' + (i / 26 % 26) as u8].repeat(4)).collect();
+		let mut param = TokenizerParameters { bin_dat: Some(bin_dat.clone().into()), trainer_chk_bytes: Some(8), ..Default::default() };
+		let exact = train_tokenizer(&mut param);
+
+		let dir = std::env::temp_dir().join(format!("test_trainer_spill_runs_{}", std::process::id()));
+		for multi_threaded in [None, Some(2)] {
+			let mut param = TokenizerParameters {
+				bin_dat: Some(bin_dat.clone().into()), trainer_chk_bytes: Some(8), multi_threaded, max_mem: Some(0x4000),
+				spill_runs: Some(crate::tok_spill::SpillRuns::new(dir.clone()).unwrap()), ..Default::default()
+			};
+			assert!(exact.keys().map(|key| entry_bytes(key.len())).sum::<usize>() > memory_share(&param).unwrap());
+			assert_eq!(train_tokenizer(&mut param), exact);
+			assert_eq!(param.pruning_metrics.budget_passes.load(Ordering::Relaxed), 0);
+		}
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+		std::fs::remove_dir(dir).unwrap();
+	}
+
+	#[test]
+	fn test_train_unigram_bytes_suffix_array() {
+		let metrics = PruningMetrics::default();
+		let byte_vec = ConcatenatedBytes::new(vec![
+				1, 2, 3, 1, 2, 3, 1, 2,
+				3, 1, 2, 3, 1, 2, 3, 1,
+				0, 0, 0, 0, 0, 9, 0, 0,
+			],
+			vec![0..8, 8..16, 16..20, 20..24]
+		);
+		let counter = train_unigram_bytes_suffix_array::<i16>(&byte_vec, None);
+		assert_eq!(counter, train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::default(), None, None, None, &metrics));
+		for max_token_len in 1..5 {
+			assert_eq!(
+				train_unigram_bytes_suffix_array::<i16>(&byte_vec, Some(max_token_len)),
+				train_unigram_bytes::<i16>(&byte_vec, PruningPolicy::default(), None, Some(max_token_len), None, &metrics)
+			);
+		}
+		assert_eq!(*counter.get(&vec![1, 2, 3]).unwrap(), 4);
+		assert_eq!(counter.get(&vec![0, 0, 0, 0, 0]), None);
+	}
+
+	#[test]
+	fn test_count_backends() {
+		let file = File::open("pexels-pixabay-302743.jpg").expect("Unable to open file");
+		let mut input = vec![];
+		BufReader::new(file).take(2048).read_to_end(&mut input).expect("Unable to read file");
+		input.extend_from_slice(b"abcabcabdabcxyzxyzabcabc");
+
+		for (chunk, max_token_len) in input.chunks(96).zip([None, Some(3)].into_iter().cycle()) {
+			let suffix_array_param = TokenizerParameters { count_backend: CountBackend::SuffixArray, max_token_len, ..Default::default() };
+			let model = greedy_bpe_encode(chunk, &suffix_array_param);
+			assert_eq!(model, greedy_bpe_encode(chunk, &TokenizerParameters { max_token_len, ..Default::default() }));
+			assert!(model.keys().all(|key| key.len() <= max_token_len.unwrap_or(usize::MAX)));
+		}
 	}
 
 	#[test]
@@ -332,6 +1069,20 @@ mod tests {
 		assert_eq!(*scores.get(&vec![3, 1]).unwrap(), 5);
 	}
 
+	#[test]
+	fn test_counter_overflow() {
+		// `count * sub.len()` is way over `i16::MAX` on a run of zeros
+		#[allow(clippy::single_range_in_vec_init)]
+		let byte_vec = ConcatenatedBytes::new(vec![0; 400], vec![0..400]);
+		let mut counter = BTreeMap::new();
+		counter.insert(vec![0; 200], 0i16);
+		let scores = numerical_grade_encodable(&byte_vec, &counter);
+		assert_eq!(*scores.get(&vec![0; 200]).unwrap(), i16::MAX);
+
+		let model = greedy_bpe_encode(&[0; 400], &TokenizerParameters::default());
+		assert_eq!(*model.get(&vec![0; 201]).unwrap(), 200 * 200);
+	}
+
 	#[test]
 	fn test_all_substring() {
 		let binary: Vec<u8> = ("a".repeat(10000) + "b" + &"a".repeat(5) + "b").bytes().collect();
@@ -372,22 +1123,144 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn test_split_chunks() {
+		let mut param = TokenizerParameters { bin_dat: Some(b"0123456789".to_vec().into()), trainer_chk_bytes: Some(4), ..Default::default() };
+		assert_eq!(split_chunks(&param), vec![(&b"0123"[..], false), (&b"4567"[..], false), (&b"89"[..], false)]);
+
+		param.trainer_chk_overlap = Some(1);
+		assert_eq!(split_chunks(&param), vec![
+			(&b"0123"[..], false), (&b"3"[..], true), (&b"3456"[..], false), (&b"6"[..], true), (&b"6789"[..], false),
+		]);
+		param.bin_dat = Some(vec![].into());
+		assert_eq!(split_chunks(&param), vec![]);
+
+		let bin_dat = b"head:0123456789;head:abcdefghij;head:0123456789;".repeat(4);
+		let param = TokenizerParameters { bin_dat: Some(bin_dat.clone().into()), trainer_chk_bytes: Some(8), chunking_mode: ChunkingMode::ContentDefined, ..Default::default() };
+		let chunks = split_chunks(&param);
+		assert_eq!(chunks.iter().map(|(chunk, _)| chunk.len()).sum::<usize>(), bin_dat.len());
+		assert!(chunks.iter().all(|&(chunk, subtract)| chunk.len() <= 16 && !subtract));
+	}
+
+	#[test]
+	fn test_file_bounds() {
+		// The files are cut on their own, and the overlap stays in a file
+		let param = TokenizerParameters {
+			bin_dat: Some(b"0123456789".to_vec().into()), file_bounds: Some(vec![0..3, 3..10]),
+			trainer_chk_bytes: Some(4), trainer_chk_overlap: Some(1), ..Default::default()
+		};
+		assert_eq!(split_chunks(&param), vec![
+			(&b"012"[..], false), (&b"3456"[..], false), (&b"6"[..], true), (&b"6789"[..], false),
+		]);
+
+		// "abcabc" is only repeated across the two files
+		let mut param = TokenizerParameters { bin_dat: Some(b"xabcabcx".to_vec().into()), trainer_chk_bytes: Some(8), ..Default::default() };
+		assert_eq!(train_tokenizer(&mut param).len(), 1);
+		param.file_bounds = Some(vec![0..4, 4..8]);
+		assert_eq!(train_tokenizer(&mut param), BTreeMap::new());
+	}
+
+	#[test]
+	fn test_chunk_overlap() {
+		// "abc" * 2 is cut in half by the chunk boundary at 8
+		let mut param = TokenizerParameters { bin_dat: Some(b"01234abcabc56789".to_vec().into()), trainer_chk_bytes: Some(8), ..Default::default() };
+		assert_eq!(train_tokenizer(&mut param), BTreeMap::new());
+		param.trainer_chk_overlap = Some(4);
+		assert_eq!(train_tokenizer(&mut param), BTreeMap::from([(b"abc".to_vec(), 4 - 3)]));
+		param.multi_threaded = Some(2);
+		assert_eq!(train_tokenizer(&mut param), BTreeMap::from([(b"abc".to_vec(), 4 - 3)]));
+
+		// Both chunks see the whole "abcabc", the overlap takes one of them back
+		let mut param = TokenizerParameters { bin_dat: Some(b"01abcabc23".to_vec().into()), trainer_chk_bytes: Some(8), ..Default::default() };
+		let model = train_tokenizer(&mut param);
+		param.trainer_chk_overlap = Some(6);
+		assert_eq!(train_tokenizer(&mut param), model);
+
+		// The chunks take "aaaaa" but the shared bytes alone take "aaaa", so "aaaaa" is counted twice,
+		// 16 from each chunk, and "aaaa" only goes below zero to be dropped
+		let param = TokenizerParameters { bin_dat: Some(vec![b'a'; 12].into()), trainer_chk_bytes: Some(8), trainer_chk_overlap: Some(5), ..Default::default() };
+		let chunks = split_chunks(&param);
+		assert_eq!(chunks.iter().map(|&(chunk, subtract)| (chunk.len(), subtract)).collect::<Vec<_>>(), vec![(8, false), (5, true), (8, false), (5, true), (6, false)]);
+		assert_eq!(greedy_bpe_encode_signed(chunks[0].0, false, &param), BTreeMap::from([(b"aaaaa".to_vec(), 16)]));
+		assert_eq!(greedy_bpe_encode_signed(chunks[1].0, true, &param), BTreeMap::from([(b"aaaa".to_vec(), -6)]));
+		let model = train_chunks_single_thread(&param, &chunks);
+		assert_eq!(model, BTreeMap::from([(b"aaaa".to_vec(), -3), (b"aaaaa".to_vec(), 32)]));
+		assert_eq!(subtract_lengths(model), BTreeMap::from([(b"aaaaa".to_vec(), 32 - 5)]));
+	}
+
+	#[test]
+	fn test_train_tokenizer_streaming() {
+		let files: [&[u8]; 3] = [b"01abcabc23abcabcXYZXYZ", b"", b"abcdabcdabcdefgabc-abcdabcd"];
+		let bin_dat = files.concat();
+		let file_bounds = vec![0..22, 22..22, 22..49];
+		let sources = || files.map(|file| Box::new(std::io::Cursor::new(file.to_vec())) as Box<dyn Read>).into_iter();
+
+		for (chunking_mode, trainer_chk_overlap, multi_threaded) in [
+			(ChunkingMode::Fixed, None, None), (ChunkingMode::Fixed, Some(3), Some(2)),
+			(ChunkingMode::ContentDefined, None, Some(2)), (ChunkingMode::ContentDefined, Some(2), None),
+		] {
+			let mut param = TokenizerParameters {
+				bin_dat: Some(bin_dat.clone().into()), file_bounds: Some(file_bounds.clone()), trainer_chk_bytes: Some(8),
+				chunking_mode, trainer_chk_overlap, multi_threaded, ..Default::default()
+			};
+			let expected = train_tokenizer(&mut param);
+			for block_bytes in [1, 5, 64] {
+				let mut counts = [0; 256];
+				let model = train_tokenizer_streaming(&param, sources(), block_bytes, &mut counts).unwrap();
+				assert_eq!(subtract_lengths(model), expected, "{:?} {:?} {}", chunking_mode, trainer_chk_overlap, block_bytes);
+				assert_eq!(counts, byte_counts(&bin_dat));
+			}
+		}
+
+		let param = TokenizerParameters { bytes_to_read: Some(10), trainer_chk_bytes: Some(8), ..Default::default() };
+		let mut counts = [0; 256];
+		train_tokenizer_streaming(&param, sources(), 4, &mut counts).unwrap();
+		assert_eq!(counts, byte_counts(b"01abcabc23"));
+		assert_eq!(prune_vocab_by_score(BTreeMap::from([(b"ab".to_vec(), 3), (b"cd".to_vec(), 5), (b"e".to_vec(), 9)]), 260).len(), 1);
+	}
+
+	#[test]
+	fn test_prune_vocab() {
+		let param = TokenizerParameters {
+			bin_dat: Some(b"abcabcabcxyzxyzab".to_vec().into()),
+			encode_mode: tok_codec::EncodeMode::MinTokens,
+			..Default::default()
+		};
+		let mut model = BTreeMap::new();
+		model.insert(b"ab".to_vec(), 100);
+		model.insert(b"abc".to_vec(), 10);
+		model.insert(b"bca".to_vec(), 50);
+		model.insert(b"xyz".to_vec(), 1);
+
+		// Everything is kept, but re-scored: abc * 3 + xyz * 2 + ab
+		let rescored = prune_vocab(&param, model.clone(), 1000);
+		assert_eq!(rescored.len(), 4);
+		assert_eq!(rescored[&b"abc".to_vec()], 3 * 2 - 3);
+		assert_eq!(rescored[&b"bca".to_vec()], -3);
+
+		let pruned = prune_vocab(&param, model.clone(), tok_codec::FIRST_LEARNED_TOKEN as usize + 2);
+		assert_eq!(pruned.keys().collect::<Vec<_>>(), vec![&b"abc".to_vec(), &b"xyz".to_vec()]);
+		assert!(prune_vocab(&param, model, 0).is_empty());
+	}
+
 	#[test]
 	fn test_train_tokenizer() {
 		{ // This will test every functions in the trainer to ensure it won't crash
 		let result_too_short = train_tokenizer(&mut TokenizerParameters {
 			multi_threaded: Some(2),
 			dbg_lv: debug_enum::DEBUG,
-			bin_dat: Some(b"a".to_vec()),
+			bin_dat: Some(b"a".to_vec().into()),
 			bytes_to_read: None,
 			trainer_chk_bytes: Some(2),
+			..Default::default()
 		});
 		let result = train_tokenizer(&mut TokenizerParameters {
 			multi_threaded: Some(2),
 			dbg_lv: debug_enum::DEBUG,
-			bin_dat: Some(b"abcdabcc".to_vec()),
+			bin_dat: Some(b"abcdabcc".to_vec().into()),
 			bytes_to_read: None,
 			trainer_chk_bytes: None,
+			..Default::default()
 		});
 
 		assert_eq!(result_too_short, BTreeMap::new());
@@ -403,17 +1276,20 @@ mod tests {
 		let mut input = vec![];
 		reader.take(bytes_to_read).read_to_end(&mut input).expect("Unable to read file");
 
-		let result = train_tokenizer(&mut TokenizerParameters {
+		// Not `output.vocab.txt`, so it won't clobber the trained model
+		let output_path = std::env::temp_dir().join(format!("test_train_tokenizer_{}.vocab.txt", std::process::id()));
+		entry(&mut TokenizerParameters {
 			multi_threaded: Some(num_cpus::get_physical()),
 			dbg_lv: debug_enum::DEBUG,
-			bin_dat: Some(input),
+			bin_dat: Some(input.into()),
 			bytes_to_read: None,
 			trainer_chk_bytes: Some(16),
+			output_path: Some(output_path.to_str().unwrap().to_string()),
+			..Default::default()
 		});
-		let mut file = File::create("output.vocab.txt").expect("create failed");
-		for (byte_vec, score) in result {
-			writeln!(file, "{:?}\t{}", byte_vec, score).expect("write failed");
-		}
+		let vocab = std::fs::read_to_string(&output_path).expect("Unable to read the vocabulary");
+		std::fs::remove_file(&output_path).unwrap();
+		assert!(vocab.lines().count() > tok_codec::FIRST_LEARNED_TOKEN as usize);
 		}
 	}
 }
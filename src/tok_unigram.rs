@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use crate::{TokenizerParameters, tok_codec::{self, ByteTrie}};
+
+
+// Used when "vs=" isn't given, the same default as SentencePiece
+pub const DEFAULT_VOCAB_SIZE: usize = 8000;
+// Longest seed piece when "ml=" isn't given
+pub const DEFAULT_MAX_PIECE_LEN: usize = 16;
+// Most frequent seed pieces kept before the EM starts
+pub const SEED_SIZE: usize = 1 << 20;
+// EM iterations between two prunings
+const EM_ITERATIONS: usize = 2;
+// Pieces expected less often than this are dropped by the EM;
+// the fallback bytes are kept at this count instead so any input stays encodable
+const MIN_EXPECTED_COUNT: f64 = 0.5;
+// A pruning keeps at least this much of the learned pieces, so the rest get re-estimated in between
+const SHRINK_FACTOR: f64 = 0.75;
+
+#[derive(Debug)]
+struct UnigramModel {
+	// The 256 fallback bytes first, then the learned pieces
+	pieces: Vec<Vec<u8>>,
+	log_probs: Vec<f64>,
+	trie: ByteTrie,
+}
+
+fn log_add_exp(a: f64, b: f64) -> f64 {
+	if a == f64::NEG_INFINITY {
+		return b;
+	}
+	let max = a.max(b);
+	max + (-(a - b).abs()).exp().ln_1p()
+}
+
+impl UnigramModel {
+	fn new(pieces: Vec<Vec<u8>>, log_probs: Vec<f64>) -> UnigramModel {
+		let mut trie = ByteTrie::new();
+		for (id, piece) in pieces.iter().enumerate() {
+			trie.insert(piece, id as u32);
+		}
+		UnigramModel { pieces, log_probs, trie }
+	}
+
+	fn from_counts(pieces: Vec<Vec<u8>>, counts: &[f64]) -> UnigramModel {
+		// The M-step: maximum likelihood from the (expected) counts
+		let (pieces, counts): (Vec<Vec<u8>>, Vec<f64>) = pieces.into_iter().zip(counts)
+			.filter(|(piece, &count)| piece.len() == 1 || count >= MIN_EXPECTED_COUNT)
+			.map(|(piece, &count)| (piece, count.max(MIN_EXPECTED_COUNT)))
+			.unzip();
+		let log_total = counts.iter().sum::<f64>().ln();
+		let log_probs = counts.iter().map(|count| count.ln() - log_total).collect();
+		UnigramModel::new(pieces, log_probs)
+	}
+
+	fn expected_counts(&self, bytes: &[u8]) -> (Vec<f64>, f64) {
+		// The E-step: forward-backward over every segmentation of the bytes,
+		// returns the expected count of each piece and the log-likelihood of the bytes
+		let n = bytes.len();
+		let mut alpha = vec![f64::NEG_INFINITY; n + 1];
+		alpha[0] = 0.0;
+		for start in 0..n {
+			for (len, id) in self.trie.prefixes(&bytes[start..]) {
+				alpha[start + len] = log_add_exp(alpha[start + len], alpha[start] + self.log_probs[id as usize]);
+			}
+		}
+
+		let log_likelihood = alpha[n];
+		let mut beta = vec![f64::NEG_INFINITY; n + 1];
+		beta[n] = 0.0;
+		let mut counts = vec![0.0; self.pieces.len()];
+		for start in (0..n).rev() {
+			for (len, id) in self.trie.prefixes(&bytes[start..]) {
+				let log_prob = self.log_probs[id as usize];
+				beta[start] = log_add_exp(beta[start], log_prob + beta[start + len]);
+				counts[id as usize] += (alpha[start] + log_prob + beta[start + len] - log_likelihood).exp();
+			}
+		}
+		(counts, log_likelihood)
+	}
+
+	fn viterbi(&self, bytes: &[u8], skip_whole: bool) -> Vec<u32> {
+		// The most likely segmentation; `skip_whole` leaves out the piece that covers all the bytes,
+		// so it gives what a piece would be split into without it
+		let mut best = vec![(f64::NEG_INFINITY, 0, 0); bytes.len() + 1];
+		best[0].0 = 0.0;
+		for start in 0..bytes.len() {
+			for (len, id) in self.trie.prefixes(&bytes[start..]) {
+				if skip_whole && len == bytes.len() {
+					continue;
+				}
+				let log_prob = best[start].0 + self.log_probs[id as usize];
+				if log_prob > best[start + len].0 {
+					best[start + len] = (log_prob, id, len);
+				}
+			}
+		}
+
+		// The fallback bytes make sure every position is reachable
+		let mut pieces = vec![];
+		let mut end = bytes.len();
+		while end > 0 {
+			let (_, id, len) = best[end];
+			pieces.push(id);
+			end -= len;
+		}
+		pieces.reverse();
+		pieces
+	}
+
+	fn prune(&self, files: &[&[u8]], learned_size: usize) -> UnigramModel {
+		// Like SentencePiece: the loss of a piece is how much the likelihood drops
+		// when it's replaced by its own best segmentation, the pieces with the least loss go
+		let mut frequencies = vec![0.0; self.pieces.len()];
+		for id in files.iter().flat_map(|file| self.viterbi(file, false)) {
+			frequencies[id as usize] += 1.0;
+		}
+		let total: f64 = frequencies.iter().sum();
+
+		let learned = tok_codec::BYTE_FALLBACK_TOKENS as usize..self.pieces.len();
+		let mut losses: Vec<(f64, usize)> = learned.clone().map(|id| {
+			let frequency = frequencies[id];
+			if frequency == 0.0 {
+				return (0.0, id);
+			}
+			let alternatives = self.viterbi(&self.pieces[id], true);
+			let new_total = total + frequency * (alternatives.len() as f64 - 1.0);
+			let log_prob = (frequency / total).ln();
+			let alternative_log_prob: f64 = alternatives.iter()
+				.map(|&alternative| ((frequencies[alternative as usize] + frequency) / new_total).ln())
+				.sum();
+			(frequency / total * (log_prob - alternative_log_prob), id)
+		}).collect();
+		losses.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+		let keep_size = learned_size.max((learned.len() as f64 * SHRINK_FACTOR) as usize);
+		let mut kept: Vec<usize> = losses.into_iter().take(keep_size).map(|(_, id)| id).collect();
+		kept.sort_unstable();
+		let ids = (0..tok_codec::BYTE_FALLBACK_TOKENS as usize).chain(kept);
+		let (pieces, log_probs) = ids.map(|id| (self.pieces[id].clone(), self.log_probs[id])).unzip();
+		UnigramModel::new(pieces, log_probs)
+	}
+}
+
+pub fn train_unigram(param: &TokenizerParameters, seeds: &BTreeMap<Vec<u8>, u64>) -> BTreeMap<Vec<u8>, f64> {
+	// SentencePiece's unigram language model: EM to estimate the piece probabilities,
+	// then prune the pieces that matter the least until the vocabulary fits
+	let bin_dat = param.bin_dat.as_deref().unwrap();
+	// Every file is a sentence of its own
+	let files: Vec<&[u8]> = param.file_bounds().into_iter().map(|file| &bin_dat[file]).collect();
+	let vocab_size = param.vocab_size.unwrap_or(DEFAULT_VOCAB_SIZE);
+	let learned_size = vocab_size.saturating_sub(tok_codec::FIRST_LEARNED_TOKEN as usize);
+
+	// Starts from the raw counts
+	let mut byte_counts = [0.0; 256];
+	for &byte in bin_dat {
+		byte_counts[byte as usize] += 1.0;
+	}
+	let (pieces, counts): (Vec<Vec<u8>>, Vec<f64>) = (0..=u8::MAX).map(|byte| (vec![byte], byte_counts[byte as usize]))
+		.chain(seeds.iter().filter(|(seed, _)| seed.len() > 1).map(|(seed, &count)| (seed.clone(), count as f64)))
+		.unzip();
+	let mut model = UnigramModel::from_counts(pieces, &counts);
+
+	loop {
+		for _ in 0..EM_ITERATIONS {
+			let mut counts = vec![0.0; model.pieces.len()];
+			let mut log_likelihood = 0.0;
+			for file in &files {
+				let (file_counts, file_log_likelihood) = model.expected_counts(file);
+				counts.iter_mut().zip(file_counts).for_each(|(count, file_count)| *count += file_count);
+				log_likelihood += file_log_likelihood;
+			}
+			model = UnigramModel::from_counts(model.pieces, &counts);
+			if param.has_debug() { println!("Debug: EM: {} pieces, log-likelihood {:.2}", model.pieces.len(), log_likelihood); }
+		}
+		if model.pieces.len() - tok_codec::BYTE_FALLBACK_TOKENS as usize <= learned_size {
+			break;
+		}
+		model = model.prune(&files, learned_size);
+	}
+	model.pieces.into_iter().zip(model.log_probs).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use crate::{tok_unigram::*, TokenizerParameters};
+
+	fn test_model(pieces: &[(&[u8], f64)]) -> UnigramModel {
+		// The learned pieces get the IDs from 256 in order
+		let (pieces, log_probs) = (0..=u8::MAX).map(|byte| (vec![byte], -10.0))
+			.chain(pieces.iter().map(|&(piece, log_prob)| (piece.to_vec(), log_prob)))
+			.unzip();
+		UnigramModel::new(pieces, log_probs)
+	}
+
+	#[test]
+	fn test_expected_counts() {
+		let model = test_model(&[(b"ab", -1.0)]);
+		let (counts, log_likelihood) = model.expected_counts(b"ab");
+		assert_eq!(counts.len(), 257);
+		// "ab" or "a" + "b"
+		let whole = (-1.0f64).exp();
+		let split = (-20.0f64).exp();
+		assert!((log_likelihood - (whole + split).ln()).abs() < 1e-9);
+		assert!((counts[b'a' as usize] - split / (whole + split)).abs() < 1e-9);
+		assert!((counts.iter().sum::<f64>() - (whole + 2.0 * split) / (whole + split)).abs() < 1e-9);
+		assert_eq!(model.expected_counts(b"").1, 0.0);
+	}
+
+	#[test]
+	fn test_viterbi() {
+		let model = test_model(&[(b"ab", -1.0), (b"abc", -5.0), (b"cd", -2.0)]);
+		// ab = 256, abc = 257, cd = 258
+		assert_eq!(model.viterbi(b"abcd", false), vec![256, 258]);
+		assert_eq!(model.viterbi(b"abc", false), vec![257]);
+		assert_eq!(model.viterbi(b"abc", true), vec![256, b'c' as u32]);
+		assert_eq!(model.viterbi(b"", false), vec![]);
+	}
+
+	#[test]
+	fn test_train_unigram() {
+		let bin_dat = b"the cat sat on the mat, the cat ate the rat. ".repeat(8);
+		let seeds: BTreeMap<Vec<u8>, u64> = [
+			(b"the ".to_vec(), 32), (b"cat ".to_vec(), 16), (b"at".to_vec(), 48),
+			(b"he".to_vec(), 32), (b"t ".to_vec(), 40), (b"q".to_vec(), 1),
+		].into_iter().collect();
+		let param = TokenizerParameters { bin_dat: Some(bin_dat.into()), vocab_size: Some(tok_codec::FIRST_LEARNED_TOKEN as usize + 2), ..Default::default() };
+		let model = train_unigram(&param, &seeds);
+
+		assert_eq!(model.len(), 256 + 2);
+		assert!(model.contains_key(b"the ".as_slice()));
+		let total: f64 = model.values().map(|log_prob| log_prob.exp()).sum();
+		assert!((total - 1.0).abs() < 1e-9);
+		assert!(model.values().all(|&log_prob| log_prob < 0.0));
+	}
+}